@@ -0,0 +1,238 @@
+//! Annotations within comments that describe how a variable is imported
+use crate::ProjectTarget;
+use std::{iter::Peekable, str::CharIndices};
+
+/// Prefix of comment lines holding annotations for the variable declared below them
+pub const DIRECTIVE_PREFIX: &str = "@bws";
+
+/// Metadata attached to a variable through annotations in its comments.
+///
+/// Annotations are written inline as `@name` or `@name=value` (e.g.
+/// `API_KEY=abc # @project=payments @note="Stripe live key"`), or without the `@` on a comment line
+/// starting with `@bws` directly above the variable (e.g. `# @bws project=payments skip`). Values
+/// containing whitespace may be wrapped in double quotes. The following annotations are supported:
+///
+/// - `project=PROJECT`: assign the secret to the project with the given ID, or to a new project with
+///   the given name. May be given more than once.
+/// - `note=TEXT`: use the given text as the secret's note, in place of any comment
+/// - `key=KEY`: import the secret under the given key
+/// - `skip`: leave the variable out of the import
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Annotations {
+    /// Projects the secret is assigned to, in place of the usual assignment
+    pub projects: Vec<ProjectTarget>,
+    /// Note replacing the comment of the variable
+    pub note: Option<String>,
+    /// Key replacing the key of the variable
+    pub key: Option<String>,
+    /// Whether the variable is left out of the import
+    pub skip: bool,
+}
+
+impl Annotations {
+    /// Combines these annotations with others given later, which take precedence where both
+    /// provide a value.
+    pub fn merge(&mut self, other: Self) {
+        self.projects.extend(other.projects);
+        self.note = other.note.or(self.note.take());
+        self.key = other.key.or(self.key.take());
+        self.skip |= other.skip;
+    }
+
+    /// Applies a single annotation with the given name and optional value.
+    fn apply(&mut self, name: &str, value: Option<String>) -> Result<(), String> {
+        match (name, value) {
+            ("project", Some(value)) => self.projects.push(
+                value
+                    .parse()
+                    .map_err(|e| format!("invalid @project annotation: {e}"))?,
+            ),
+            ("note", Some(value)) => self.note = Some(value),
+            ("key", Some(value)) if !value.is_empty() => self.key = Some(value),
+            ("skip", None) => self.skip = true,
+            ("project" | "note" | "key", _) => {
+                return Err(format!("@{name} annotation requires a value"))
+            }
+            ("skip", Some(_)) => return Err("@skip annotation does not take a value".to_owned()),
+            _ => return Err(format!("unknown annotation @{name}")),
+        }
+        Ok(())
+    }
+}
+
+/// Extracts the `@name[=value]` annotations from the text of an inline comment, returning the
+/// remaining text of the comment.
+///
+/// An `@` only starts an annotation at the beginning of the comment or after whitespace, so that
+/// text such as email addresses is left as it is.
+pub(crate) fn extract_inline(
+    comment: &str,
+    annotations: &mut Annotations,
+) -> Result<String, String> {
+    let mut remaining = String::with_capacity(comment.len());
+    let mut chars = comment.char_indices().peekable();
+    let mut at_word_start = true;
+
+    while let Some((_, c)) = chars.next() {
+        if c == '@' && at_word_start && chars.peek().is_some_and(|(_, c)| is_name_char(*c)) {
+            let (name, value) = take_annotation(comment, &mut chars)?;
+            annotations.apply(name, value)?;
+            continue;
+        }
+
+        at_word_start = c.is_whitespace();
+        remaining.push(c);
+    }
+
+    // Collapse the whitespace left behind by removed annotations
+    Ok(remaining.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Parses the text of a comment line, returning the annotations it holds if it is a directive line
+/// starting with [`DIRECTIVE_PREFIX`].
+pub(crate) fn parse_directive(comment: &str) -> Option<Result<Annotations, String>> {
+    let rest = comment.trim().strip_prefix(DIRECTIVE_PREFIX)?;
+    if rest.starts_with(|c: char| !c.is_whitespace()) {
+        return None;
+    }
+
+    let mut annotations = Annotations::default();
+    let mut chars = rest.char_indices().peekable();
+    let result = loop {
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break Ok(annotations);
+        }
+
+        match take_annotation(rest, &mut chars) {
+            Ok((name, value)) => {
+                if let Err(e) = annotations.apply(name, value) {
+                    break Err(e);
+                }
+            }
+            Err(e) => break Err(e),
+        }
+    };
+
+    Some(result)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Consumes an annotation of the form `name` or `name=value` from the iterator, where the value
+/// may be wrapped in double quotes.
+fn take_annotation<'t>(
+    text: &'t str,
+    chars: &mut Peekable<CharIndices<'t>>,
+) -> Result<(&'t str, Option<String>), String> {
+    let start = chars.peek().map_or(text.len(), |(i, _)| *i);
+    let mut end = start;
+    while let Some((i, c)) = chars.next_if(|(_, c)| is_name_char(*c)) {
+        end = i + c.len_utf8();
+    }
+    let name = &text[start..end];
+    if name.is_empty() {
+        let token = text[start..].split_whitespace().next().unwrap_or_default();
+        return Err(format!("expected an annotation, found {token:?}"));
+    }
+
+    if chars.next_if(|(_, c)| *c == '=').is_none() {
+        return match chars.peek() {
+            None => Ok((name, None)),
+            Some((_, c)) if c.is_whitespace() => Ok((name, None)),
+            Some((i, _)) => Err(format!(
+                "unexpected characters after annotation @{name}: {:?}",
+                &text[*i..]
+            )),
+        };
+    }
+
+    let mut value = String::new();
+    if chars.next_if(|(_, c)| *c == '"').is_some() {
+        loop {
+            match chars.next() {
+                Some((_, '"')) => break,
+                Some((_, '\\')) if chars.peek().is_some_and(|(_, c)| matches!(c, '"' | '\\')) => {
+                    value.extend(chars.next().map(|(_, c)| c));
+                }
+                Some((_, c)) => value.push(c),
+                None => {
+                    return Err(format!(
+                        "quoted value of @{name} annotation is never closed"
+                    ))
+                }
+            }
+        }
+    } else {
+        while let Some((_, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+            value.push(c);
+        }
+    }
+
+    Ok((name, Some(value)))
+}
+
+#[cfg(test)]
+mod annotation_tests {
+    use super::*;
+
+    fn extract(comment: &str) -> Result<(String, Annotations), String> {
+        let mut annotations = Annotations::default();
+        let remaining = extract_inline(comment, &mut annotations)?;
+        Ok((remaining, annotations))
+    }
+
+    #[test_case::test_case("Plain comment" => Ok(("Plain comment".to_owned(), Annotations::default())); "leaves plain comments")]
+    #[test_case::test_case("Mail me@example.com" => Ok(("Mail me@example.com".to_owned(), Annotations::default())); "ignores at sign within words")]
+    #[test_case::test_case("@project=payments" => Ok((String::new(), Annotations { projects: vec![ProjectTarget::NewProject("payments".to_owned())], ..Default::default() })); "extracts project")]
+    #[test_case::test_case(r#"Live key @note="Stripe \"live\" key" @skip"# => Ok(("Live key".to_owned(), Annotations { note: Some(r#"Stripe "live" key"#.to_owned()), skip: true, ..Default::default() })); "extracts quoted note and flag")]
+    #[test_case::test_case("@key=STRIPE_KEY trailing text" => Ok(("trailing text".to_owned(), Annotations { key: Some("STRIPE_KEY".to_owned()), ..Default::default() })); "keeps text around annotations")]
+    #[test_case::test_case("@project=a @project=00000000-0000-0000-0000-000000000001" => Ok((String::new(), Annotations { projects: vec![ProjectTarget::NewProject("a".to_owned()), ProjectTarget::ProjectId(uuid::Uuid::from_u128(1))], ..Default::default() })); "extracts several projects")]
+    #[test_case::test_case("@unknown" => Err("unknown annotation @unknown".to_owned()); "rejects unknown annotation")]
+    #[test_case::test_case("@note" => Err("@note annotation requires a value".to_owned()); "rejects missing value")]
+    #[test_case::test_case("@skip=yes" => Err("@skip annotation does not take a value".to_owned()); "rejects unexpected value")]
+    #[test_case::test_case(r#"@note="never closed"# => Err("quoted value of @note annotation is never closed".to_owned()); "rejects unterminated quote")]
+    fn extracts_inline_annotations(comment: &str) -> Result<(String, Annotations), String> {
+        extract(comment)
+    }
+
+    #[test_case::test_case(" @bws project=payments skip" => Some(Ok(Annotations { projects: vec![ProjectTarget::NewProject("payments".to_owned())], skip: true, ..Default::default() })); "parses directive")]
+    #[test_case::test_case("@bws" => Some(Ok(Annotations::default())); "parses empty directive")]
+    #[test_case::test_case("@bws note" => Some(Err("@note annotation requires a value".to_owned())); "rejects invalid directive")]
+    #[test_case::test_case("@bws \"quoted\"" => Some(Err("expected an annotation, found \"\\\"quoted\\\"\"".to_owned())); "rejects text in directive")]
+    #[test_case::test_case("@bwsproject=a" => None; "requires whitespace after prefix")]
+    #[test_case::test_case("Regular comment" => None; "ignores regular comments")]
+    fn parses_directives(comment: &str) -> Option<Result<Annotations, String>> {
+        parse_directive(comment)
+    }
+
+    #[test]
+    fn later_annotations_take_precedence() {
+        let mut annotations = Annotations {
+            projects: vec![ProjectTarget::NewProject("a".to_owned())],
+            note: Some("first".to_owned()),
+            key: Some("FIRST".to_owned()),
+            skip: true,
+        };
+        annotations.merge(Annotations {
+            projects: vec![ProjectTarget::NewProject("b".to_owned())],
+            note: Some("second".to_owned()),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            annotations,
+            Annotations {
+                projects: vec![
+                    ProjectTarget::NewProject("a".to_owned()),
+                    ProjectTarget::NewProject("b".to_owned())
+                ],
+                note: Some("second".to_owned()),
+                key: Some("FIRST".to_owned()),
+                skip: true,
+            }
+        );
+    }
+}
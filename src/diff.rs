@@ -0,0 +1,353 @@
+//! Comparison of secrets against those already stored in Bitwarden Secrets Manager
+use crate::{ImportPayload, Project, Secret};
+use std::{collections::HashMap, fmt};
+use uuid::Uuid;
+
+/// Placeholder shown in place of secret values, unless values are revealed
+const MASK: &str = "********";
+
+/// A difference between a secret as it exists and as it is wanted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretChange {
+    /// The secret doesn't exist yet
+    Added(Secret),
+    /// The secret exists, but isn't wanted
+    Removed(Secret),
+    /// The secret exists with a different value or note
+    Changed { old: Secret, new: Secret },
+}
+
+impl SecretChange {
+    /// The key of the changed secret
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Added(secret) | Self::Removed(secret) | Self::Changed { new: secret, .. } => {
+                &secret.key
+            }
+        }
+    }
+
+    /// Determines whether the value of an existing secret has changed.
+    pub fn value_changed(&self) -> bool {
+        matches!(self, Self::Changed { old, new } if old.value != new.value)
+    }
+
+    /// Determines whether the note of an existing secret has changed.
+    pub fn note_changed(&self) -> bool {
+        matches!(self, Self::Changed { old, new } if old.note != new.note)
+    }
+
+    /// Describes the change on a single line, masking secret values unless `show_values` is set.
+    pub fn describe(&self, show_values: bool) -> String {
+        let value = |secret: &Secret| match show_values {
+            true => format!("{:?}", secret.value),
+            false => MASK.to_owned(),
+        };
+
+        match self {
+            Self::Added(secret) => format!("+ {}={}", secret.key, value(secret)),
+            Self::Removed(secret) => format!("- {}={}", secret.key, value(secret)),
+            Self::Changed { old, new } => {
+                let mut changes = vec![];
+                if self.value_changed() {
+                    changes.push(format!("value {} -> {}", value(old), value(new)));
+                }
+                if self.note_changed() {
+                    changes.push(format!("note {:?} -> {:?}", old.note, new.note));
+                }
+                format!("~ {}: {}", new.key, changes.join(", "))
+            }
+        }
+    }
+}
+
+/// The changes to the secrets of a single project
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectDiff {
+    /// ID of the project, or none for secrets that aren't assigned to any project
+    pub project_id: Option<Uuid>,
+    /// Name of the project, if known
+    pub name: Option<String>,
+    pub changes: Vec<SecretChange>,
+}
+
+impl fmt::Display for ProjectDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.name, self.project_id) {
+            (Some(name), Some(id)) => write!(f, "Project {name:?} ({id})"),
+            (None, Some(id)) => write!(f, "Project {id}"),
+            (_, None) => write!(f, "Unassigned secrets"),
+        }
+    }
+}
+
+/// The differences between the secrets that exist in Bitwarden Secrets Manager, and the secrets that
+/// are wanted, grouped by project.
+///
+/// Secrets are matched by their key within each project. New projects that are wanted are matched
+/// with existing projects of the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadDiff {
+    /// Changes for each project having any, in the order the projects first appear
+    pub projects: Vec<ProjectDiff>,
+    incremental: ImportPayload,
+}
+
+impl PayloadDiff {
+    /// Compares the secrets that are wanted with those that exist. Changes to notes are ignored
+    /// unless `compare_notes` is set.
+    pub fn new(wanted: &ImportPayload, existing: &ImportPayload, compare_notes: bool) -> Self {
+        // Use existing projects in place of new projects of the same name
+        let mut project_ids = HashMap::new();
+        let mut new_projects = vec![];
+        for project in &wanted.projects {
+            match existing
+                .projects
+                .iter()
+                .find(|p| p.id == project.id || p.name == project.name)
+            {
+                Some(existing) => project_ids.insert(project.id, existing.id),
+                None => {
+                    new_projects.push(project.clone());
+                    project_ids.insert(project.id, project.id)
+                }
+            };
+        }
+        let wanted_secrets = wanted
+            .secrets
+            .iter()
+            .map(|secret| Secret {
+                project_ids: secret
+                    .project_ids
+                    .iter()
+                    .map(|id| project_ids.get(id).copied().unwrap_or(*id))
+                    .collect(),
+                ..secret.clone()
+            })
+            .collect::<Vec<_>>();
+
+        // Group the secrets of both sides by project
+        let mut groups: Vec<(Option<Uuid>, Vec<usize>, Vec<&Secret>)> = vec![];
+        let mut group_of =
+            |project_id: Option<Uuid>| match groups.iter().position(|(id, ..)| *id == project_id) {
+                Some(index) => index,
+                None => {
+                    groups.push((project_id, vec![], vec![]));
+                    groups.len() - 1
+                }
+            };
+        let mut wanted_groups = vec![];
+        for (index, secret) in wanted_secrets.iter().enumerate() {
+            for project_id in secret.project_groups() {
+                wanted_groups.push((group_of(project_id), index));
+            }
+        }
+        let mut existing_groups = vec![];
+        for secret in &existing.secrets {
+            for project_id in secret.project_groups() {
+                existing_groups.push((group_of(project_id), secret));
+            }
+        }
+        for (group, index) in wanted_groups {
+            groups[group].1.push(index);
+        }
+        for (group, secret) in existing_groups {
+            groups[group].2.push(secret);
+        }
+
+        // Compare the secrets of each project by key, noting which wanted secrets must be imported
+        let mut imported: Vec<Option<Secret>> = vec![None; wanted_secrets.len()];
+        let mut projects = vec![];
+        for (project_id, wanted_indices, mut existing_secrets) in groups {
+            let mut changes = vec![];
+            for index in wanted_indices {
+                let new = &wanted_secrets[index];
+                match existing_secrets.iter().position(|old| old.key == new.key) {
+                    Some(position) => {
+                        let old = existing_secrets.remove(position);
+                        // Keep the ID of the secret being replaced, along with its note if notes
+                        // aren't compared
+                        let new = Secret {
+                            id: old.id,
+                            note: match compare_notes {
+                                true => new.note.clone(),
+                                false => old.note.clone(),
+                            },
+                            ..new.clone()
+                        };
+                        if old.value != new.value || old.note != new.note {
+                            imported[index] = Some(new.clone());
+                            changes.push(SecretChange::Changed {
+                                old: old.clone(),
+                                new,
+                            });
+                        }
+                    }
+                    None => {
+                        imported[index].get_or_insert_with(|| new.clone());
+                        changes.push(SecretChange::Added(new.clone()));
+                    }
+                }
+            }
+            changes.extend(
+                existing_secrets
+                    .into_iter()
+                    .cloned()
+                    .map(SecretChange::Removed),
+            );
+
+            if !changes.is_empty() {
+                let name = project_id.and_then(|id| {
+                    existing
+                        .projects
+                        .iter()
+                        .chain(&new_projects)
+                        .find(|project| project.id == id)
+                        .map(|project| project.name.clone())
+                });
+                projects.push(ProjectDiff {
+                    project_id,
+                    name,
+                    changes,
+                });
+            }
+        }
+
+        // Only new and changed secrets are imported
+        let secrets = imported.into_iter().flatten().collect::<Vec<_>>();
+        let projects_used = new_projects
+            .into_iter()
+            .filter(|project| secrets.iter().any(|s| s.project_ids.contains(&project.id)))
+            .collect::<Vec<Project>>();
+
+        Self {
+            projects,
+            incremental: ImportPayload {
+                projects: projects_used,
+                secrets,
+            },
+        }
+    }
+
+    /// Determines whether the wanted secrets are the same as the existing secrets.
+    pub fn is_empty(&self) -> bool {
+        self.projects.is_empty()
+    }
+
+    /// Iterates over all changes, across all projects.
+    pub fn changes(&self) -> impl Iterator<Item = &SecretChange> {
+        self.projects.iter().flat_map(|project| &project.changes)
+    }
+
+    /// A payload holding only the secrets that are new or changed, so that importing it doesn't
+    /// duplicate secrets that are already up to date. Only the new projects that these secrets are
+    /// assigned to are declared.
+    pub fn incremental_payload(&self) -> &ImportPayload {
+        &self.incremental
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+    use crate::import_payload::fixtures::{project, secret};
+
+    #[test]
+    fn finds_changes_per_project() {
+        let existing_project = project(1, "existing");
+        let existing = ImportPayload {
+            projects: vec![existing_project.clone()],
+            secrets: vec![
+                secret("SAME", 10, &[1]).with_value("1"),
+                secret("VALUE", 11, &[1]).with_value("1"),
+                secret("NOTE", 12, &[1]).with_value("1").with_note("old"),
+                secret("REMOVED", 13, &[1]).with_value("1"),
+                secret("UNASSIGNED", 14, &[]).with_value("1"),
+            ],
+        };
+
+        // The wanted project has its own ID, but is matched with the existing project by name
+        let wanted_project = project(2, "existing");
+        let new_project = project(3, "new");
+        let wanted = ImportPayload {
+            projects: vec![wanted_project, new_project.clone()],
+            secrets: vec![
+                secret("SAME", 20, &[2]).with_value("1"),
+                secret("VALUE", 21, &[2]).with_value("2"),
+                secret("NOTE", 22, &[2]).with_value("1").with_note("new"),
+                secret("ADDED", 23, &[2, 3]).with_value("1"),
+                secret("UNASSIGNED", 24, &[]).with_value("1"),
+            ],
+        };
+
+        let diff = PayloadDiff::new(&wanted, &existing, true);
+        let summary = diff
+            .projects
+            .iter()
+            .map(|project| {
+                (
+                    project.project_id,
+                    project
+                        .changes
+                        .iter()
+                        .map(|change| change.describe(true))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    Some(existing_project.id),
+                    vec![
+                        r#"~ VALUE: value "1" -> "2""#.to_owned(),
+                        r#"~ NOTE: note "old" -> "new""#.to_owned(),
+                        r#"+ ADDED="1""#.to_owned(),
+                        r#"- REMOVED="1""#.to_owned(),
+                    ]
+                ),
+                (Some(new_project.id), vec![r#"+ ADDED="1""#.to_owned()]),
+            ]
+        );
+
+        // Changed secrets keep their existing IDs, and existing projects aren't declared again
+        let incremental = diff.incremental_payload();
+        assert_eq!(incremental.projects, vec![new_project.clone()]);
+        let keys = incremental
+            .secrets
+            .iter()
+            .map(|secret| secret.key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["VALUE", "NOTE", "ADDED"]);
+        assert_eq!(incremental.secrets[0].id, existing.secrets[1].id);
+        assert_eq!(
+            incremental.secrets[2].project_ids,
+            vec![existing_project.id, new_project.id]
+        );
+    }
+
+    #[test]
+    fn ignores_notes_unless_compared() {
+        let existing = ImportPayload {
+            projects: vec![],
+            secrets: vec![secret("KEY", 1, &[]).with_note("note")],
+        };
+        let wanted = ImportPayload {
+            projects: vec![],
+            secrets: vec![secret("KEY", 2, &[])],
+        };
+
+        assert!(PayloadDiff::new(&wanted, &existing, false).is_empty());
+        assert!(!PayloadDiff::new(&wanted, &existing, true).is_empty());
+    }
+
+    #[test]
+    fn masks_values_by_default() {
+        let change = SecretChange::Changed {
+            old: secret("KEY", 1, &[]).with_value("old"),
+            new: secret("KEY", 1, &[]).with_value("new"),
+        };
+        assert_eq!(change.describe(false), "~ KEY: value ******** -> ********");
+    }
+}
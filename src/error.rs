@@ -0,0 +1,126 @@
+//! Structured errors describing problems encountered while parsing `.env` files
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// The reason an entry of a `.env` file could not be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The entry is not a comment, yet contains no `KEY=VALUE` assignment
+    MissingAssignment,
+    /// A quoted value was opened but never closed
+    UnterminatedQuote,
+    /// Something other than a comment follows the closing quote of a value
+    TrailingCharacters,
+}
+
+impl ParseErrorKind {
+    /// Whether a lenient parser can still make sense of the entry by taking its value literally,
+    /// rather than skipping the entry entirely
+    pub fn is_recoverable(self) -> bool {
+        matches!(self, Self::UnterminatedQuote | Self::TrailingCharacters)
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingAssignment => write!(f, "expected an assignment of the form KEY=VALUE"),
+            Self::UnterminatedQuote => write!(f, "quoted value is never closed"),
+            Self::TrailingCharacters => write!(f, "unexpected characters after closing quote"),
+        }
+    }
+}
+
+/// An error pointing at the location of a problem within a `.env` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Path of the file containing the problem, if parsed from a file
+    pub path: Option<PathBuf>,
+    /// Line number of the problem, starting from 1
+    pub line: usize,
+    /// Column number of the problem in characters, starting from 1
+    pub column: usize,
+    /// The full line of text containing the problem
+    pub text: String,
+}
+
+impl ParseError {
+    /// Constructs an error of the given kind located at a byte offset within an entry of text,
+    /// where the entry may span multiple lines.
+    pub(crate) fn new(kind: ParseErrorKind, entry: &str, offset: usize) -> Self {
+        let before = &entry[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = entry[offset..]
+            .find('\n')
+            .map_or(entry.len(), |i| offset + i);
+
+        Self {
+            kind,
+            path: None,
+            line: before.matches('\n').count() + 1,
+            column: entry[line_start..offset].chars().count() + 1,
+            text: entry[line_start..line_end].to_owned(),
+        }
+    }
+
+    /// Shifts the location of this error to account for the line at which its entry starts
+    /// within a file, and the path of that file.
+    pub(crate) fn located(mut self, first_line: usize, path: Option<PathBuf>) -> Self {
+        self.line += first_line - 1;
+        self.path = path;
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}:", path.to_string_lossy())?,
+            None => write!(f, "<input>:")?,
+        }
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line,
+            self.column,
+            self.kind,
+            self.text.trim()
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Describes a failure to open or read the file at the given path
+pub(crate) fn load_error(path: &Path, e: io::Error) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Failed to load file at {path}: {e}",
+        path = path.to_string_lossy()
+    )
+}
+
+#[cfg(test)]
+mod parse_error_tests {
+    use super::*;
+
+    #[test_case::test_case("KEY", 0 => (1, 1, "KEY".to_owned()); "locates start of single line")]
+    #[test_case::test_case("KEY=\"a\" b", 7 => (1, 8, "KEY=\"a\" b".to_owned()); "locates column within single line")]
+    #[test_case::test_case("KEY=\"é\nb\" c", 10 => (2, 3, "b\" c".to_owned()); "locates position on later line")]
+    fn locates_error(entry: &str, offset: usize) -> (usize, usize, String) {
+        let error = ParseError::new(ParseErrorKind::MissingAssignment, entry, offset);
+        (error.line, error.column, error.text)
+    }
+
+    #[test]
+    fn displays_location() {
+        let error = ParseError::new(ParseErrorKind::MissingAssignment, "  invalid line", 2)
+            .located(5, Some(PathBuf::from(".env")));
+        assert_eq!(
+            error.to_string(),
+            ".env:5:3: expected an assignment of the form KEY=VALUE: invalid line"
+        );
+    }
+}
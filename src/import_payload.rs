@@ -1,7 +1,22 @@
 //! Structured representation of Bitwarden Secrets Manager import JSON format
-use crate::{DotEnvFile, EnvVar};
+use crate::{
+    error::load_error,
+    project_listing,
+    validation::{enforce_policy, validate_secret, PayloadIssue},
+    Annotations, DotEnvFile, DuplicatePolicy, EnvVar, ProjectTarget, Quote, RoutingRules,
+    ValidationPolicy,
+};
+use anyhow::anyhow;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
 use uuid::Uuid;
 
+/// Namespace from which deterministic IDs are derived when no seed is given
+pub const DEFAULT_ID_NAMESPACE: Uuid = Uuid::from_u128(0x69624137_b1a0_551f_b3a6_62f68cf29ce4);
+
 /// Represents a single project as found in the Bitwarden Secrets Manager import JSON format.
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Project {
@@ -21,18 +36,44 @@ pub struct Secret {
 }
 
 impl Secret {
-    /// Parses an individual secret from a given [`EnvVar`] with an optional `project_id`.
-    fn from_env_var(value: EnvVar, project_id: Option<uuid::Uuid>) -> Self {
+    /// The projects the secret is grouped under when comparing keys, which is none for unassigned
+    /// secrets.
+    pub(crate) fn project_groups(&self) -> Vec<Option<Uuid>> {
+        match self.project_ids.as_slice() {
+            [] => vec![None],
+            ids => ids.iter().copied().map(Some).collect(),
+        }
+    }
+
+    /// Parses an individual secret from a given [`EnvVar`], assigned to the given projects.
+    fn from_env_var(value: EnvVar, project_ids: Vec<uuid::Uuid>) -> Self {
         Self {
             key: value.key,
             value: value.value,
             note: value.comment.unwrap_or_default(),
-            project_ids: project_id.map_or(vec![], |id| vec![id]),
+            project_ids,
             id: value.temp_id,
         }
     }
 }
 
+/// Converts a secret back into an environment variable, using its note as the comment.
+impl From<Secret> for EnvVar {
+    fn from(value: Secret) -> Self {
+        Self {
+            key: value.key,
+            value: value.value,
+            comment: Some(value.note).filter(|note| !note.is_empty()),
+            temp_id: value.id,
+            quote: Quote::None,
+            line: None,
+            source: None,
+            section: None,
+            annotations: Annotations::default(),
+        }
+    }
+}
+
 /// Represents the entirety of the Bitwarden Secrets Manager import JSON format.
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct ImportPayload {
@@ -40,42 +81,622 @@ pub struct ImportPayload {
     pub secrets: Vec<Secret>,
 }
 
+/// Options controlling how an [`ImportPayload`] is written out as JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputOptions {
+    /// Sort secrets by key, and the project IDs of each secret, rather than keeping input order
+    pub sort_secrets: bool,
+    /// Sort projects by name, rather than keeping the order in which they were declared
+    pub sort_projects: bool,
+    /// Write JSON on a single line, rather than indented over several lines
+    pub compact: bool,
+    /// End the output with a newline
+    pub trailing_newline: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            sort_secrets: false,
+            sort_projects: false,
+            compact: false,
+            trailing_newline: true,
+        }
+    }
+}
+
+impl OutputOptions {
+    /// Options that write the same bytes for the same secrets and projects, regardless of the order
+    /// in which they were given. Combined with [`ImportPayload::derive_ids`], this makes the output
+    /// depend only on the logical contents of the input.
+    pub fn canonical() -> Self {
+        Self {
+            sort_secrets: true,
+            sort_projects: true,
+            ..Default::default()
+        }
+    }
+}
+
 /// The way in which all new secrets may (or may not) be assigned to projects in Bitwarden Secrets
 /// Manager.
 pub enum ProjectAssignment {
     None,
     Existing(uuid::Uuid),
     New(String),
+    /// Define a new project for each section of the file, named after the section. Secrets declared
+    /// outside of any section are left unassigned.
+    NewPerSection,
+    /// Assign each secret to projects according to rules matching its key
+    Rules(RoutingRules),
+    /// Assign every secret to all of the given projects
+    Multiple(Vec<ProjectTarget>),
 }
 
 impl ImportPayload {
+    /// Reads an import or export JSON file.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if file cannot be read, or if its contents are not in the Bitwarden Secrets
+    /// Manager JSON format.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let raw = fs::read_to_string(path).map_err(|e| load_error(path, e))?;
+
+        serde_json::from_str(&raw).map_err(|e| {
+            anyhow!(
+                "Failed to read {path} as Bitwarden Secrets Manager JSON: {e}",
+                path = path.to_string_lossy()
+            )
+        })
+    }
+
     /// Constructs a new representation of the import JSON from a parsed [`DotEnvFile`] using the
     /// provided [`ProjectAssignment`] strategy.
+    ///
+    /// Secrets that are annotated with projects are assigned to those projects instead.
     pub fn from_dotenv(dotenv: DotEnvFile, project_assignment: ProjectAssignment) -> Self {
+        Self::from_dotenvs([(dotenv, project_assignment)])
+    }
+
+    /// Constructs a single representation of the import JSON from several parsed [`DotEnvFile`]s,
+    /// each using its own [`ProjectAssignment`] strategy.
+    ///
+    /// Files that are assigned to a new project of the same name share a single project.
+    pub fn from_dotenvs(
+        dotenvs: impl IntoIterator<Item = (DotEnvFile, ProjectAssignment)>,
+    ) -> Self {
         // Empty vector of projects means no projects are to be created
         let mut projects: Vec<Project> = vec![];
+        let mut secrets: Vec<Secret> = vec![];
+
+        for (dotenv, project_assignment) in dotenvs {
+            // Determine the IDs of the projects that all secrets of the file will be assigned to
+            let assigned_ids = match &project_assignment {
+                // If existing case, assign the provided ID to the project
+                ProjectAssignment::Existing(id) => vec![*id],
+                // If new case, create a new project declaration (unless one with the same name was
+                // already declared) and assign the ID to the project
+                ProjectAssignment::New(name) => vec![new_project(&mut projects, name)],
+                // If multiple case, do either of the above for each project
+                ProjectAssignment::Multiple(targets) => target_ids(&mut projects, targets),
+                // Otherwise, projects are either assigned to each secret individually, or not at all
+                ProjectAssignment::NewPerSection
+                | ProjectAssignment::Rules(_)
+                | ProjectAssignment::None => vec![],
+            };
+
+            for env_var in dotenv.iter() {
+                let project_ids = match &project_assignment {
+                    // If the secret is annotated with projects, assign to those projects instead
+                    _ if !env_var.annotations.projects.is_empty() => {
+                        target_ids(&mut projects, &env_var.annotations.projects)
+                    }
+                    // If per section case, assign to a new project named after the secret's section
+                    ProjectAssignment::NewPerSection => env_var
+                        .section
+                        .iter()
+                        .map(|name| new_project(&mut projects, name))
+                        .collect(),
+                    // If rules case, assign to the projects targeted by the rules matching its key
+                    ProjectAssignment::Rules(rules) => {
+                        target_ids(&mut projects, rules.targets_for(&env_var.key))
+                    }
+                    _ => assigned_ids.clone(),
+                };
 
-        // Determine the ID of the project that all secrets will be assigned to (if any)
-        let assigned_id = match project_assignment {
-            // If existing case, assign the provided ID to the project
-            ProjectAssignment::Existing(id) => Some(id),
-            // If new case, create a new project declaration with random UUID and assign the ID to
-            // the project
-            ProjectAssignment::New(name) => {
-                let id = Uuid::new_v4();
-                projects.push(Project { id, name });
-                Some(id)
+                secrets.push(Secret::from_env_var(env_var.clone(), project_ids));
             }
-            // If none case, assign no project ID to the secrets
-            ProjectAssignment::None => None,
+        }
+
+        Self { projects, secrets }
+    }
+}
+
+/// Finds the ID of the new project with the given name, declaring the project with a random UUID if
+/// it hasn't been declared yet.
+fn new_project(projects: &mut Vec<Project>, name: &str) -> Uuid {
+    match projects.iter().find(|project| project.name == name) {
+        Some(project) => project.id,
+        None => {
+            let id = Uuid::new_v4();
+            projects.push(Project {
+                id,
+                name: name.to_owned(),
+            });
+            id
+        }
+    }
+}
+
+/// Finds the IDs of the given projects, declaring any new projects that haven't been declared yet.
+/// Projects targeted more than once are only included once.
+fn target_ids<'a>(
+    projects: &mut Vec<Project>,
+    targets: impl IntoIterator<Item = &'a ProjectTarget>,
+) -> Vec<Uuid> {
+    let mut ids = vec![];
+    for target in targets {
+        let id = match target {
+            ProjectTarget::ProjectId(id) => *id,
+            ProjectTarget::NewProject(name) => new_project(projects, name),
         };
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    ids
+}
 
-        Self {
-            projects,
-            secrets: dotenv
+impl ImportPayload {
+    /// Combines several payloads into one, in order.
+    ///
+    /// Projects with the same ID or name are merged into the first of them, and the secrets of the
+    /// later projects are assigned to it instead. Secrets that share an ID are given new IDs.
+    ///
+    /// When more than one secret with the same key is assigned to the same project (or left
+    /// unassigned), the collision is handled according to the given [`DuplicatePolicy`], and
+    /// reported as a warning. Secrets whose values and notes are identical are merged without being
+    /// reported. A secret that loses a collision is only removed from the project the collision is
+    /// in, and is dropped once it has no projects left. Projects sharing an ID but not a name are
+    /// also reported as a warning, and keep the name they were first given.
+    ///
+    /// Returns the merged payload along with the warnings.
+    ///
+    /// # Errors
+    ///
+    /// Will return error listing every collision if the policy is [`DuplicatePolicy::Error`].
+    pub fn merge(
+        payloads: impl IntoIterator<Item = Self>,
+        policy: DuplicatePolicy,
+        verbose: bool,
+    ) -> anyhow::Result<(Self, Vec<String>)> {
+        let mut projects: Vec<Project> = vec![];
+        let mut secrets: Vec<Secret> = vec![];
+        let mut dropped = HashSet::new();
+        let mut secret_ids = HashSet::new();
+        let mut owners: HashMap<(Option<Uuid>, String), usize> = HashMap::new();
+        let mut collisions = vec![];
+        let mut warnings = vec![];
+        let mut payload_count = 0;
+
+        for payload in payloads {
+            payload_count += 1;
+
+            // Find the merged project that each project of the payload is merged into
+            let mut project_ids = HashMap::new();
+            for project in payload.projects {
+                match projects
+                    .iter()
+                    .find(|p| p.id == project.id || p.name == project.name)
+                {
+                    Some(merged) => {
+                        if merged.id == project.id && merged.name != project.name {
+                            warnings.push(format!(
+                                "Project {id} is named both {first:?} and {second:?}",
+                                id = merged.id,
+                                first = merged.name,
+                                second = project.name
+                            ));
+                        }
+                        project_ids.insert(project.id, merged.id)
+                    }
+                    None => {
+                        let id = project.id;
+                        projects.push(project);
+                        project_ids.insert(id, id)
+                    }
+                };
+            }
+
+            for mut secret in payload.secrets {
+                let mut assigned = vec![];
+                for id in &secret.project_ids {
+                    let id = project_ids.get(id).copied().unwrap_or(*id);
+                    if !assigned.contains(&id) {
+                        assigned.push(id);
+                    }
+                }
+                secret.project_ids = assigned;
+
+                while !secret_ids.insert(secret.id) {
+                    secret.id = Uuid::new_v4();
+                }
+
+                let index = secrets.len();
+                for group in secret.project_groups() {
+                    let owner = match owners.get(&(group, secret.key.clone())) {
+                        Some(owner) => *owner,
+                        None => {
+                            owners.insert((group, secret.key.clone()), index);
+                            continue;
+                        }
+                    };
+
+                    let location = match group {
+                        Some(id) => format!("project {id}"),
+                        None => "unassigned secrets".to_owned(),
+                    };
+                    let identical =
+                        secrets[owner].value == secret.value && secrets[owner].note == secret.note;
+                    let (action, loser) = match policy {
+                        _ if identical => (None, Some(index)),
+                        DuplicatePolicy::Error => {
+                            collisions.push(format!("{:?} in {location}", secret.key));
+                            continue;
+                        }
+                        DuplicatePolicy::KeepFirst => {
+                            (Some("keeping first definition"), Some(index))
+                        }
+                        DuplicatePolicy::KeepLast => {
+                            owners.insert((group, secret.key.clone()), index);
+                            (Some("keeping last definition"), Some(owner))
+                        }
+                        DuplicatePolicy::KeepAll => (Some("keeping all definitions"), None),
+                    };
+                    if let Some(action) = action {
+                        warnings.push(format!(
+                            "{:?} is defined more than once in {location} ({action})",
+                            secret.key
+                        ));
+                    }
+                    let Some(loser) = loser else {
+                        continue;
+                    };
+
+                    // Remove the losing secret from the project, dropping it if it has none left
+                    let loser_secret = if loser == index {
+                        &mut secret
+                    } else {
+                        &mut secrets[loser]
+                    };
+                    loser_secret.project_ids.retain(|id| Some(*id) != group);
+                    if loser_secret.project_ids.is_empty() {
+                        dropped.insert(loser);
+                    }
+                }
+
+                secrets.push(secret);
+            }
+        }
+
+        if !collisions.is_empty() {
+            return Err(anyhow!(
+                "Found {} key collision(s):\n  {}",
+                collisions.len(),
+                collisions.join("\n  ")
+            ));
+        }
+
+        let mut positions = 0..;
+        secrets.retain(|_| positions.next().is_some_and(|i| !dropped.contains(&i)));
+
+        if verbose {
+            eprintln!(
+                "Merged {payload_count} payload(s) into {} project(s) and {} secret(s)",
+                projects.len(),
+                secrets.len()
+            );
+        }
+
+        Ok((Self { projects, secrets }, warnings))
+    }
+
+    /// Replaces the random IDs of the new projects and secrets with UUIDs derived from the given
+    /// namespace, so that converting the same input again produces the same payload.
+    ///
+    /// Project IDs are derived from the project's name, and secret IDs from the secret's key along
+    /// with the IDs of its projects, in any order. Secrets that would otherwise share an ID, such
+    /// as duplicate keys that were kept, are told apart by the order in which they appear, so sort
+    /// the secrets first (see [`ImportPayload::sort_secrets`]) for IDs that don't depend on it.
+    pub fn derive_ids(&mut self, namespace: &Uuid) {
+        let mut derived = HashMap::new();
+        for project in &mut self.projects {
+            let id = Uuid::new_v5(namespace, format!("project/{}", project.name).as_bytes());
+            derived.insert(project.id, id);
+            project.id = id;
+        }
+
+        let mut used = HashSet::new();
+        for secret in &mut self.secrets {
+            for project_id in &mut secret.project_ids {
+                if let Some(id) = derived.get(project_id) {
+                    *project_id = *id;
+                }
+            }
+
+            let mut project_ids = secret
+                .project_ids
                 .iter()
-                .map(|v| Secret::from_env_var(v.clone(), assigned_id))
-                .collect(),
+                .map(Uuid::to_string)
+                .collect::<Vec<_>>();
+            project_ids.sort();
+            let project_ids = project_ids.join(",");
+            let name = format!("secret/{project_ids}/{}", secret.key);
+            let mut id = Uuid::new_v5(namespace, name.as_bytes());
+            let mut occurrence = 1;
+            while !used.insert(id) {
+                occurrence += 1;
+                id = Uuid::new_v5(namespace, format!("{name}#{occurrence}").as_bytes());
+            }
+            secret.id = id;
+        }
+    }
+
+    /// Sorts the project IDs of each secret, then sorts secrets by key, project IDs, value and note,
+    /// so that the order doesn't depend on the order of the input.
+    pub fn sort_secrets(&mut self) {
+        self.secrets
+            .iter_mut()
+            .for_each(|secret| secret.project_ids.sort());
+        self.secrets.sort_by(|a, b| {
+            (&a.key, &a.project_ids, &a.value, &a.note).cmp(&(
+                &b.key,
+                &b.project_ids,
+                &b.value,
+                &b.note,
+            ))
+        });
+    }
+
+    /// Sorts projects by name, keeping projects with the same name in their current order.
+    pub fn sort_projects(&mut self) {
+        self.projects.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /// Writes the payload as JSON according to the given options.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if the payload cannot be serialized.
+    pub fn to_json_with_options(&self, options: &OutputOptions) -> anyhow::Result<String> {
+        let mut payload = self.clone();
+        if options.sort_secrets {
+            payload.sort_secrets();
+        }
+        if options.sort_projects {
+            payload.sort_projects();
+        }
+
+        let mut output = if options.compact {
+            serde_json::to_string(&payload)?
+        } else {
+            serde_json::to_string_pretty(&payload)?
+        };
+        if options.trailing_newline {
+            output.push('\n');
+        }
+
+        Ok(output)
+    }
+
+    /// Derives a namespace for [`ImportPayload::derive_ids`] from a user-provided seed, so that
+    /// different seeds produce different IDs for the same input.
+    pub fn id_namespace(seed: &str) -> Uuid {
+        Uuid::new_v5(&DEFAULT_ID_NAMESPACE, seed.as_bytes())
+    }
+
+    /// Checks the payload the way the Bitwarden Secrets Manager importer would, returning all
+    /// problems found.
+    ///
+    /// Projects that secrets are assigned to, but that aren't defined in the payload, must already
+    /// exist. If the IDs of the existing projects are given, assignments to any other project are
+    /// reported as [`PayloadIssue::UnknownProject`]. Otherwise, each such project is reported once as
+    /// [`PayloadIssue::ExternalProject`].
+    pub fn validate(&self, existing_project_ids: Option<&[Uuid]>) -> Vec<PayloadIssue> {
+        let mut issues = vec![];
+
+        let mut project_ids = HashSet::new();
+        for project in &self.projects {
+            if !project_ids.insert(project.id) {
+                issues.push(PayloadIssue::DuplicateProjectId(project.id));
+            }
+        }
+
+        let mut secret_ids = HashSet::new();
+        let mut keys = HashSet::new();
+        let mut external = HashSet::new();
+        for secret in &self.secrets {
+            if !secret_ids.insert(secret.id) {
+                issues.push(PayloadIssue::DuplicateSecretId(secret.id));
+            }
+
+            issues.extend(
+                validate_secret(&secret.key, &secret.value, &secret.note)
+                    .into_iter()
+                    .map(|issue| PayloadIssue::Secret {
+                        key: secret.key.clone(),
+                        issue,
+                    }),
+            );
+
+            // A project listed twice is reported as a duplicate assignment below, not as a
+            // duplicate key
+            let mut groups = HashSet::new();
+            for project_id in secret.project_groups() {
+                if groups.insert(project_id) && !keys.insert((project_id, secret.key.as_str())) {
+                    issues.push(PayloadIssue::DuplicateKey {
+                        key: secret.key.clone(),
+                        project_id,
+                    });
+                }
+            }
+
+            let mut assigned = HashSet::new();
+            for &project_id in &secret.project_ids {
+                if !assigned.insert(project_id) {
+                    issues.push(PayloadIssue::DuplicateProjectAssignment {
+                        key: secret.key.clone(),
+                        project_id,
+                    });
+                    continue;
+                }
+
+                if project_ids.contains(&project_id) {
+                    continue;
+                }
+                match existing_project_ids {
+                    Some(existing) if !existing.contains(&project_id) => {
+                        issues.push(PayloadIssue::UnknownProject {
+                            key: secret.key.clone(),
+                            project_id,
+                        });
+                    }
+                    Some(_) => {}
+                    None => {
+                        if external.insert(project_id) {
+                            issues.push(PayloadIssue::ExternalProject(project_id));
+                        }
+                    }
+                }
+            }
+        }
+
+        issues
+    }
+
+    /// Checks the keys, values and notes of all secrets the same way as
+    /// [`DotEnvFile::validate`], handling any problems according to the given [`ValidationPolicy`].
+    /// Returns a warning for each problem that was fixed or only warned about.
+    ///
+    /// # Errors
+    ///
+    /// Will return error listing every problem found if the policy is [`ValidationPolicy::Error`],
+    /// or every problem that could not be fixed if the policy is [`ValidationPolicy::Fix`].
+    pub fn validate_secrets(&mut self, policy: ValidationPolicy) -> anyhow::Result<Vec<String>> {
+        let mut warnings = vec![];
+        let mut failures = vec![];
+
+        self.secrets.retain_mut(|secret| {
+            enforce_policy(
+                policy,
+                &mut secret.key,
+                &secret.value,
+                &mut secret.note,
+                &mut warnings,
+                &mut failures,
+            )
+        });
+
+        if !failures.is_empty() {
+            return Err(anyhow!(
+                "Found {} problem(s) with secrets:\n  {}",
+                failures.len(),
+                failures.join("\n  ")
+            ));
+        }
+
+        Ok(warnings)
+    }
+
+    /// Finds the single project with the given name.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if no project, or more than one project, has the given name.
+    pub fn find_project_by_name(&self, name: &str) -> anyhow::Result<&Project> {
+        project_listing::find_by_name(&self.projects, name)
+    }
+
+    /// Returns all secrets assigned to the project with the given ID.
+    pub fn secrets_in_project(&self, id: Uuid) -> impl Iterator<Item = &Secret> {
+        self.secrets
+            .iter()
+            .filter(move |secret| secret.project_ids.contains(&id))
+    }
+
+    /// Keeps only the secrets that secrets assigned to the given project would be compared with:
+    /// the secrets of that project, or the unassigned secrets if no project is given. A new project
+    /// that isn't in the payload yet has no secrets.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if more than one project has the name of the given new project.
+    pub fn secrets_for_target(&self, target: Option<&ProjectTarget>) -> anyhow::Result<Self> {
+        let project_id = match target {
+            Some(ProjectTarget::ProjectId(id)) => Some(*id),
+            Some(ProjectTarget::NewProject(name)) => {
+                if !self.projects.iter().any(|project| project.name == *name) {
+                    return Ok(Self {
+                        projects: self.projects.clone(),
+                        secrets: vec![],
+                    });
+                }
+                Some(self.find_project_by_name(name)?.id)
+            }
+            None => None,
+        };
+
+        let secrets = self
+            .secrets
+            .iter()
+            .filter(|secret| match project_id {
+                Some(id) => secret.project_ids.contains(&id),
+                None => secret.project_ids.is_empty(),
+            })
+            .cloned()
+            .collect();
+
+        Ok(Self {
+            projects: self.projects.clone(),
+            secrets,
+        })
+    }
+}
+
+/// Builders for the secrets and projects used throughout the tests
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    /// Builds a secret with the given key, ID and project IDs, holding a placeholder value
+    pub(crate) fn secret(key: &str, id: u128, project_ids: &[u128]) -> Secret {
+        Secret {
+            key: key.to_owned(),
+            value: "value".to_owned(),
+            note: String::new(),
+            project_ids: project_ids.iter().copied().map(Uuid::from_u128).collect(),
+            id: Uuid::from_u128(id),
+        }
+    }
+
+    /// Builds a project with the given ID and name
+    pub(crate) fn project(id: u128, name: &str) -> Project {
+        Project {
+            id: Uuid::from_u128(id),
+            name: name.to_owned(),
+        }
+    }
+
+    impl Secret {
+        pub(crate) fn with_value(mut self, value: &str) -> Self {
+            self.value = value.to_owned();
+            self
+        }
+
+        pub(crate) fn with_note(mut self, note: &str) -> Self {
+            self.note = note.to_owned();
+            self
         }
     }
 }
@@ -84,7 +705,11 @@ impl ImportPayload {
 mod payload_tests {
     use fake::{Fake, Faker};
 
-    use super::*;
+    use super::{
+        fixtures::{project, secret},
+        *,
+    };
+    use crate::{dotenv::DEFAULT_SECTION_PATTERN, ParseOptions};
 
     #[test]
     fn leaves_project_blank_on_secrets_when_none_supplied() {
@@ -127,12 +752,560 @@ mod payload_tests {
         });
     }
 
+    #[test]
+    fn combines_files_with_separate_projects() {
+        let first = Faker.fake::<DotEnvFile>();
+        let second = Faker.fake::<DotEnvFile>();
+        let third = Faker.fake::<DotEnvFile>();
+        let existing_id = Faker.fake::<Uuid>();
+        let counts = [first.len(), second.len(), third.len()];
+
+        let payload = ImportPayload::from_dotenvs([
+            (first, ProjectAssignment::New("shared".to_owned())),
+            (second, ProjectAssignment::Existing(existing_id)),
+            (third, ProjectAssignment::New("shared".to_owned())),
+        ]);
+
+        // Files assigned to the same new project share it
+        assert_eq!(payload.projects.len(), 1);
+        let shared_id = payload.projects[0].id;
+
+        assert_eq!(payload.secrets.len(), counts.iter().sum::<usize>());
+        assert_eq!(
+            payload.secrets_in_project(shared_id).count(),
+            counts[0] + counts[2]
+        );
+        assert_eq!(payload.secrets_in_project(existing_id).count(), counts[1]);
+    }
+
+    #[test]
+    fn defines_new_project_per_section() {
+        let options = ParseOptions {
+            section_pattern: Some(DEFAULT_SECTION_PATTERN.to_owned()),
+            ..Default::default()
+        };
+        let dotenv = DotEnvFile::parse_from_str_with_options(
+            "NONE=1\n# [project: first]\nA=1\n# [project: second]\nB=1\n# [project: first]\nC=1",
+            &options,
+        )
+        .expect("failed to parse");
+        let payload = ImportPayload::from_dotenv(dotenv, ProjectAssignment::NewPerSection);
+
+        // One new project per distinct section name
+        let names = payload
+            .projects
+            .iter()
+            .map(|project| project.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["first", "second"]);
+        let (first, second) = (payload.projects[0].id, payload.projects[1].id);
+
+        let assigned = payload
+            .secrets
+            .iter()
+            .map(|secret| secret.project_ids.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            assigned,
+            vec![vec![], vec![first], vec![second], vec![first]]
+        );
+    }
+
+    #[test]
+    fn assigns_projects_by_rules() {
+        let existing_id = Faker.fake::<Uuid>();
+        let rules = RoutingRules {
+            rules: vec![
+                "SERVICE_1_*=Service 1".parse().expect("invalid rule"),
+                format!("SERVICE_2_*={existing_id}")
+                    .parse()
+                    .expect("invalid rule"),
+            ],
+            ..Default::default()
+        };
+        let dotenv = DotEnvFile::parse_from_str(
+            "SERVICE_1_A=1\nSERVICE_2_A=1\nSHARED=1\nSERVICE_1_B=1",
+            false,
+            false,
+        )
+        .expect("failed to parse");
+        let payload = ImportPayload::from_dotenv(dotenv, ProjectAssignment::Rules(rules));
+
+        // Only new projects are declared, once each
+        assert_eq!(payload.projects.len(), 1);
+        assert_eq!(payload.projects[0].name, "Service 1");
+        let new_id = payload.projects[0].id;
+
+        let assigned = payload
+            .secrets
+            .iter()
+            .map(|secret| secret.project_ids.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            assigned,
+            vec![vec![new_id], vec![existing_id], vec![], vec![new_id]]
+        );
+    }
+
+    #[test]
+    fn assigns_secrets_to_multiple_projects() {
+        let existing_id = Faker.fake::<Uuid>();
+        let payload = ImportPayload::from_dotenv(
+            Faker.fake::<DotEnvFile>(),
+            ProjectAssignment::Multiple(vec![
+                ProjectTarget::NewProject("first".to_owned()),
+                ProjectTarget::ProjectId(existing_id),
+                ProjectTarget::NewProject("second".to_owned()),
+                ProjectTarget::NewProject("first".to_owned()),
+            ]),
+        );
+
+        // Each new project is declared once
+        let names = payload
+            .projects
+            .iter()
+            .map(|project| project.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["first", "second"]);
+
+        // All secrets assigned to every distinct project
+        let expected = vec![payload.projects[0].id, existing_id, payload.projects[1].id];
+        payload
+            .secrets
+            .iter()
+            .for_each(|secret| assert_eq!(secret.project_ids, expected));
+    }
+
+    #[test]
+    fn assigns_annotated_projects_in_place_of_strategy() {
+        let options = ParseOptions {
+            annotations: true,
+            ..Default::default()
+        };
+        let dotenv = DotEnvFile::parse_from_str_with_options(
+            "A=1 # @project=annotated\nB=1\n# @bws project=annotated project=other\nC=1",
+            &options,
+        )
+        .expect("failed to parse");
+        let payload = ImportPayload::from_dotenv(dotenv, ProjectAssignment::New("all".to_owned()));
+
+        let names = payload
+            .projects
+            .iter()
+            .map(|project| project.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["all", "annotated", "other"]);
+        let ids = payload.projects.iter().map(|p| p.id).collect::<Vec<_>>();
+
+        let assigned = payload
+            .secrets
+            .iter()
+            .map(|secret| secret.project_ids.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            assigned,
+            vec![vec![ids[1]], vec![ids[0]], vec![ids[1], ids[2]]]
+        );
+    }
+
+    #[test]
+    fn derives_same_ids_for_same_input() {
+        let build = |namespace: &Uuid| {
+            let dotenv =
+                DotEnvFile::parse_from_str("A=1\nB=2\nA=3", false, false).expect("failed to parse");
+            let mut payload = ImportPayload::from_dotenv(
+                dotenv,
+                ProjectAssignment::Multiple(vec![
+                    ProjectTarget::NewProject("first".to_owned()),
+                    ProjectTarget::ProjectId(Uuid::from_u128(1)),
+                ]),
+            );
+            payload.derive_ids(namespace);
+            payload
+        };
+
+        let payload = build(&DEFAULT_ID_NAMESPACE);
+        assert_eq!(payload, build(&DEFAULT_ID_NAMESPACE));
+
+        // Secrets are assigned to the derived IDs of new projects, and existing IDs are kept
+        let project_id = payload.projects[0].id;
+        payload.secrets.iter().for_each(|secret| {
+            assert_eq!(secret.project_ids, vec![project_id, Uuid::from_u128(1)])
+        });
+
+        // Duplicate keys are given IDs of their own
+        let secret_ids = payload
+            .secrets
+            .iter()
+            .map(|secret| secret.id)
+            .collect::<HashSet<_>>();
+        assert_eq!(secret_ids.len(), 3);
+
+        // Other seeds lead to other IDs
+        let seeded = build(&ImportPayload::id_namespace("seed"));
+        assert_ne!(seeded.projects[0].id, project_id);
+        assert_ne!(seeded.secrets[0].id, payload.secrets[0].id);
+    }
+
+    #[test]
+    fn writes_same_canonical_output_regardless_of_order() {
+        let build = |input: &str| {
+            let options = ParseOptions {
+                annotations: true,
+                ..Default::default()
+            };
+            let dotenv =
+                DotEnvFile::parse_from_str_with_options(input, &options).expect("failed to parse");
+            let mut payload = ImportPayload::from_dotenv(dotenv, ProjectAssignment::None);
+            payload.sort_secrets();
+            payload.derive_ids(&DEFAULT_ID_NAMESPACE);
+            payload
+                .to_json_with_options(&OutputOptions::canonical())
+                .expect("failed to serialize")
+        };
+
+        assert_eq!(
+            build("B=2 # @project=b @project=a\nA=1 # @project=a"),
+            build("A=1 # @project=a\nB=2 # @project=a @project=b"),
+        );
+
+        // Duplicate keys are told apart by their values rather than by their order
+        assert_eq!(build("A=1\nB=2\nA=3"), build("A=3\nA=1\nB=2"));
+    }
+
+    #[test_case::test_case(OutputOptions::default() => "{\n  \"projects\": [],\n  \"secrets\": []\n}\n"; "writes pretty output by default")]
+    #[test_case::test_case(OutputOptions { compact: true, ..Default::default() } => "{\"projects\":[],\"secrets\":[]}\n"; "writes compact output")]
+    #[test_case::test_case(OutputOptions { compact: true, trailing_newline: false, ..Default::default() } => "{\"projects\":[],\"secrets\":[]}"; "omits trailing newline")]
+    fn formats_output(options: OutputOptions) -> String {
+        ImportPayload {
+            projects: vec![],
+            secrets: vec![],
+        }
+        .to_json_with_options(&options)
+        .expect("failed to serialize")
+    }
+
+    #[test]
+    fn sorts_secrets_and_projects() {
+        let dotenv =
+            DotEnvFile::parse_from_str("B=1\nA=1\nB=2", false, false).expect("failed to parse");
+        let mut payload = ImportPayload::from_dotenv(
+            dotenv,
+            ProjectAssignment::Multiple(vec![
+                ProjectTarget::NewProject("second".to_owned()),
+                ProjectTarget::NewProject("first".to_owned()),
+            ]),
+        );
+        payload.sort_secrets();
+        payload.sort_projects();
+
+        let secrets = payload
+            .secrets
+            .iter()
+            .map(|secret| (secret.key.as_str(), secret.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(secrets, vec![("A", "1"), ("B", "1"), ("B", "2")]);
+        assert!(payload.secrets[0].project_ids.is_sorted());
+
+        let projects = payload
+            .projects
+            .iter()
+            .map(|project| project.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(projects, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn derives_secret_ids_regardless_of_project_order() {
+        let mut payload = ImportPayload {
+            projects: vec![],
+            secrets: vec![secret("A", 1, &[1, 2]), secret("A", 2, &[2, 1])],
+        };
+        payload.derive_ids(&DEFAULT_ID_NAMESPACE);
+
+        // Both secrets are derived from the same key and projects, so they're told apart by order
+        let mut expected = ImportPayload {
+            projects: vec![],
+            secrets: vec![secret("A", 3, &[2, 1])],
+        };
+        expected.derive_ids(&DEFAULT_ID_NAMESPACE);
+        assert_eq!(payload.secrets[0].id, expected.secrets[0].id);
+        assert_ne!(payload.secrets[1].id, payload.secrets[0].id);
+        assert_eq!(payload.secrets[1].project_ids, [2, 1].map(Uuid::from_u128));
+    }
+
+    #[test]
+    fn selects_secrets_for_target() {
+        let payload = ImportPayload {
+            projects: vec![project(1, "project")],
+            secrets: vec![secret("A", 10, &[1]), secret("B", 11, &[])],
+        };
+        let keys = |target: Option<ProjectTarget>| {
+            payload
+                .secrets_for_target(target.as_ref())
+                .expect("failed to select secrets")
+                .secrets
+                .into_iter()
+                .map(|secret| secret.key)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            keys(Some(ProjectTarget::ProjectId(Uuid::from_u128(1)))),
+            ["A"]
+        );
+        assert_eq!(
+            keys(Some(ProjectTarget::NewProject("project".to_owned()))),
+            ["A"]
+        );
+        assert_eq!(keys(None), ["B"]);
+
+        // Projects that don't exist yet have no secrets, not even unassigned ones
+        assert!(keys(Some(ProjectTarget::NewProject("missing".to_owned()))).is_empty());
+    }
+
+    #[test]
+    fn validates_payload() {
+        let payload = ImportPayload {
+            projects: vec![
+                project(1, "project"),
+                project(2, "project"),
+                project(1, "project"),
+            ],
+            secrets: vec![
+                secret("A", 10, &[1, 2]),
+                secret("A", 11, &[2]),
+                secret("B", 11, &[3]),
+                secret("MY-KEY", 12, &[3, 4]),
+                secret("C", 13, &[1, 1]),
+                secret("D", 14, &[]),
+                secret("D", 15, &[]),
+            ],
+        };
+
+        // Projects missing from the file are assumed to exist, unless told otherwise
+        assert_eq!(
+            payload.validate(None),
+            vec![
+                PayloadIssue::DuplicateProjectId(Uuid::from_u128(1)),
+                PayloadIssue::DuplicateKey {
+                    key: "A".to_owned(),
+                    project_id: Some(Uuid::from_u128(2))
+                },
+                PayloadIssue::DuplicateSecretId(Uuid::from_u128(11)),
+                PayloadIssue::ExternalProject(Uuid::from_u128(3)),
+                PayloadIssue::Secret {
+                    key: "MY-KEY".to_owned(),
+                    issue: crate::validation::ValidationIssue::InvalidKey
+                },
+                PayloadIssue::ExternalProject(Uuid::from_u128(4)),
+                PayloadIssue::DuplicateProjectAssignment {
+                    key: "C".to_owned(),
+                    project_id: Uuid::from_u128(1)
+                },
+                PayloadIssue::DuplicateKey {
+                    key: "D".to_owned(),
+                    project_id: None
+                },
+            ]
+        );
+        assert_eq!(
+            payload.validate(Some(&[Uuid::from_u128(3)]))[3..],
+            [
+                PayloadIssue::Secret {
+                    key: "MY-KEY".to_owned(),
+                    issue: crate::validation::ValidationIssue::InvalidKey
+                },
+                PayloadIssue::UnknownProject {
+                    key: "MY-KEY".to_owned(),
+                    project_id: Uuid::from_u128(4)
+                },
+                PayloadIssue::DuplicateProjectAssignment {
+                    key: "C".to_owned(),
+                    project_id: Uuid::from_u128(1)
+                },
+                PayloadIssue::DuplicateKey {
+                    key: "D".to_owned(),
+                    project_id: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_secrets_with_policy() {
+        let mut payload = ImportPayload {
+            projects: vec![],
+            secrets: vec![
+                secret("VALID", 1, &[]),
+                secret("MY-KEY", 2, &[]),
+                secret("", 3, &[]),
+            ],
+        };
+
+        let err = payload
+            .clone()
+            .validate_secrets(ValidationPolicy::Error)
+            .expect_err("validation should fail");
+        assert!(err.to_string().starts_with("Found 2 problem(s)"), "{err}");
+
+        let warnings = payload
+            .validate_secrets(ValidationPolicy::Fix)
+            .expect("fixing should not fail");
+        assert_eq!(warnings.len(), 2);
+        let keys = payload
+            .secrets
+            .iter()
+            .map(|secret| secret.key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["VALID", "MY_KEY"]);
+    }
+
+    #[test]
+    fn accepts_generated_payload() {
+        let payload = ImportPayload::from_dotenv(
+            DotEnvFile::parse_from_file(std::path::PathBuf::from("sample.env"), false, false)
+                .expect("failed to parse"),
+            ProjectAssignment::New("sample".to_owned()),
+        );
+        assert_eq!(payload.validate(Some(&[])), vec![]);
+    }
+
+    #[test]
+    fn merges_projects_by_name_and_id() {
+        let first = ImportPayload {
+            projects: vec![project(1, "shared"), project(2, "first")],
+            secrets: vec![secret("A", 10, &[1, 2])],
+        };
+        let second = ImportPayload {
+            projects: vec![project(3, "shared"), project(2, "renamed")],
+            secrets: vec![secret("B", 10, &[3, 2, 1]), secret("C", 11, &[4])],
+        };
+
+        let (merged, warnings) =
+            ImportPayload::merge([first, second], DuplicatePolicy::Error, false)
+                .expect("failed to merge");
+        assert_eq!(
+            merged.projects,
+            vec![project(1, "shared"), project(2, "first")]
+        );
+        assert_eq!(
+            warnings,
+            vec![format!(
+                "Project {} is named both \"first\" and \"renamed\"",
+                Uuid::from_u128(2)
+            )]
+        );
+
+        // Secrets are assigned to the merged projects, and given new IDs if theirs are taken
+        assert_eq!(merged.secrets.len(), 3);
+        assert_eq!(
+            merged.secrets[1].project_ids,
+            vec![Uuid::from_u128(1), Uuid::from_u128(2)]
+        );
+        assert_ne!(merged.secrets[1].id, Uuid::from_u128(10));
+        assert_eq!(merged.secrets[2].project_ids, vec![Uuid::from_u128(4)]);
+    }
+
+    #[test_case::test_case(DuplicatePolicy::KeepFirst => matches Some(secrets) if secrets == ["KEY=first [1]", "OTHER=same []", "KEY=second [2]"]; "keeps first secret in project")]
+    #[test_case::test_case(DuplicatePolicy::KeepLast => matches Some(secrets) if secrets == ["OTHER=same []", "KEY=second [1, 2]"]; "keeps last secret in project")]
+    #[test_case::test_case(DuplicatePolicy::KeepAll => matches Some(secrets) if secrets == ["KEY=first [1]", "OTHER=same []", "KEY=second [1, 2]"]; "keeps all secrets")]
+    #[test_case::test_case(DuplicatePolicy::Error => None; "fails on collision")]
+    fn handles_key_collisions(policy: DuplicatePolicy) -> Option<Vec<String>> {
+        let first = ImportPayload {
+            projects: vec![],
+            secrets: vec![
+                secret("KEY", 10, &[1]).with_value("first"),
+                secret("OTHER", 11, &[]).with_value("same"),
+            ],
+        };
+        let second = ImportPayload {
+            projects: vec![],
+            secrets: vec![
+                secret("KEY", 12, &[1, 2]).with_value("second"),
+                // Identical secrets are merged regardless of policy
+                secret("OTHER", 13, &[]).with_value("same"),
+            ],
+        };
+
+        let (merged, _) = ImportPayload::merge([first, second], policy, false).ok()?;
+        Some(
+            merged
+                .secrets
+                .iter()
+                .map(|secret| {
+                    let project_ids = secret
+                        .project_ids
+                        .iter()
+                        .map(|id| id.as_u128())
+                        .collect::<Vec<_>>();
+                    format!("{}={} {project_ids:?}", secret.key, secret.value)
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn finds_project_by_name() {
+        let mut payload = ImportPayload::from_dotenv(
+            Faker.fake::<DotEnvFile>(),
+            ProjectAssignment::New("first".to_owned()),
+        );
+        let first = payload.projects[0].clone();
+
+        assert_eq!(
+            payload
+                .find_project_by_name("first")
+                .expect("could not find project"),
+            &first
+        );
+        assert!(payload.find_project_by_name("missing").is_err());
+
+        payload.projects.push(Project {
+            id: Uuid::new_v4(),
+            name: "first".to_owned(),
+        });
+        assert!(payload.find_project_by_name("first").is_err());
+    }
+
+    #[test]
+    fn filters_secrets_by_project() {
+        let project_id = Faker.fake::<Uuid>();
+        let mut payload = ImportPayload::from_dotenv(
+            Faker.fake::<DotEnvFile>(),
+            ProjectAssignment::Existing(project_id),
+        );
+        let assigned = payload.secrets.len();
+        payload.secrets.extend(
+            ImportPayload::from_dotenv(Faker.fake::<DotEnvFile>(), ProjectAssignment::None).secrets,
+        );
+
+        assert_eq!(payload.secrets_in_project(project_id).count(), assigned);
+    }
+
+    #[test]
+    fn converts_secrets_back_to_env_vars() {
+        let payload: ImportPayload =
+            serde_json::from_str(include_str!("../sample.json")).expect("invalid sample");
+        let dotenv = DotEnvFile::from_secrets(&payload.secrets);
+
+        assert_eq!(dotenv.len(), payload.secrets.len());
+        dotenv
+            .iter()
+            .zip(&payload.secrets)
+            .for_each(|(env_var, secret)| {
+                assert_eq!(env_var.key, secret.key);
+                assert_eq!(env_var.value, secret.value);
+                assert_eq!(env_var.comment.clone().unwrap_or_default(), secret.note);
+                assert_eq!(env_var.temp_id, secret.id);
+            });
+    }
+
     #[test]
     fn sets_existing_project_for_secrets() {
         let dotenv = Faker.fake::<DotEnvFile>();
         let project_id = Faker.fake::<Uuid>();
-        let payload =
-            ImportPayload::from_dotenv(dotenv, ProjectAssignment::Existing(project_id.clone()));
+        let payload = ImportPayload::from_dotenv(dotenv, ProjectAssignment::Existing(project_id));
 
         // No new projects listed
         assert_eq!(payload.projects.len(), 0);
@@ -0,0 +1,300 @@
+//! Expansion of `$VAR` and `${VAR}` references within the values of a `.env` file
+//!
+//! References are only resolved against variables defined above the referencing one, so a
+//! variable referencing itself (`A=${A}`) or one defined further down is left unresolved and
+//! reported, rather than looping.
+use crate::EnvVar;
+use anyhow::anyhow;
+use std::{collections::HashMap, fmt, iter::Peekable, str::CharIndices};
+
+/// Resolves variable references within the values of a set of [`EnvVar`]s.
+///
+/// A reference is resolved against the closest definition of that key above the referencing
+/// variable, as a shell would. If the key isn't defined above it, the process environment is
+/// consulted (when enabled). As references only ever point upwards, they cannot form a cycle.
+pub(crate) struct Interpolator<'a> {
+    vars: &'a [EnvVar],
+    use_process_env: bool,
+    /// Fully expanded values, indexed by the position of the variable they belong to
+    expanded: HashMap<usize, String>,
+    /// References that could not be resolved, in the order they were found
+    pub(crate) unresolved: Vec<Unresolved>,
+}
+
+/// A reference that could not be resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Unresolved {
+    /// Key of the variable holding the reference
+    pub(crate) key: String,
+    /// Name of the referenced variable
+    pub(crate) reference: String,
+    /// Whether the referenced variable is defined further down, where it cannot be resolved from
+    pub(crate) defined_below: bool,
+}
+
+/// Describes why the reference could not be resolved, in the form of a warning.
+impl fmt::Display for Unresolved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { key, reference, .. } = self;
+        if key == reference {
+            write!(f, "{key} references itself, which is not defined above it")
+        } else if self.defined_below {
+            write!(
+                f,
+                "{key} references ${{{reference}}}, which is only defined further down"
+            )
+        } else {
+            write!(f, "{key} references ${{{reference}}}, which is not defined")
+        }
+    }
+}
+
+impl<'a> Interpolator<'a> {
+    pub(crate) fn new(vars: &'a [EnvVar], use_process_env: bool) -> Self {
+        Self {
+            vars,
+            use_process_env,
+            expanded: HashMap::new(),
+            unresolved: vec![],
+        }
+    }
+
+    /// Returns the fully expanded value of the variable at the given position.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if a `${VAR:?error}` reference could not be resolved.
+    pub(crate) fn expand(&mut self, index: usize) -> anyhow::Result<String> {
+        if let Some(value) = self.expanded.get(&index) {
+            return Ok(value.clone());
+        }
+
+        let var = &self.vars[index];
+        let value = if var.quote.allows_expansion() {
+            self.expand_str(index, &var.value)?
+        } else {
+            var.value.clone()
+        };
+
+        self.expanded.insert(index, value.clone());
+        Ok(value)
+    }
+
+    /// Expands all references within the given text, which belongs to the variable at the given
+    /// position.
+    fn expand_str(&mut self, index: usize, text: &str) -> anyhow::Result<String> {
+        let mut output = String::with_capacity(text.len());
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                // Escaped dollar signs are kept literally
+                '\\' if chars.peek().is_some_and(|(_, next)| *next == '$') => {
+                    output.push('$');
+                    chars.next();
+                }
+                '$' => match chars.peek() {
+                    Some((_, '{')) => match find_closing_brace(text, i + 2) {
+                        Some(end) => {
+                            let expression = &text[i + 2..end];
+                            output.push_str(&self.expand_braced(index, expression)?);
+                            // Skip over the expression and its closing brace
+                            while chars.next_if(|(j, _)| *j <= end).is_some() {}
+                        }
+                        // Unterminated expressions are kept as written
+                        None => output.push(c),
+                    },
+                    Some((_, next)) if is_name_start(*next) => {
+                        let name = take_name(text, &mut chars);
+                        let resolved = self.resolve(index, name)?;
+                        output.push_str(&resolved.unwrap_or_else(|| {
+                            self.report_unresolved(index, name);
+                            String::new()
+                        }));
+                    }
+                    _ => output.push(c),
+                },
+                c => output.push(c),
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Expands the contents of a `${...}` expression, supporting the `${VAR}`, `${VAR:-default}`
+    /// and `${VAR:?error}` forms.
+    fn expand_braced(&mut self, index: usize, expression: &str) -> anyhow::Result<String> {
+        if let Some((name, default)) = expression.split_once(":-") {
+            return match self.resolve(index, name)? {
+                Some(value) if !value.is_empty() => Ok(value),
+                _ => self.expand_str(index, default),
+            };
+        }
+
+        if let Some((name, message)) = expression.split_once(":?") {
+            return match self.resolve(index, name)? {
+                Some(value) if !value.is_empty() => Ok(value),
+                _ => Err(anyhow!(
+                    "Failed to expand {key}: {name}: {message}",
+                    key = self.vars[index].key,
+                    message = if message.is_empty() {
+                        "parameter null or not set"
+                    } else {
+                        message
+                    }
+                )),
+            };
+        }
+
+        Ok(self.resolve(index, expression)?.unwrap_or_else(|| {
+            self.report_unresolved(index, expression);
+            String::new()
+        }))
+    }
+
+    /// Looks up the value of the given name on behalf of the variable at the given position.
+    fn resolve(&mut self, index: usize, name: &str) -> anyhow::Result<Option<String>> {
+        match self.vars[..index].iter().rposition(|v| v.key == name) {
+            Some(i) => self.expand(i).map(Some),
+            None if self.use_process_env => Ok(std::env::var(name).ok()),
+            None => Ok(None),
+        }
+    }
+
+    fn report_unresolved(&mut self, index: usize, name: &str) {
+        self.unresolved.push(Unresolved {
+            key: self.vars[index].key.clone(),
+            reference: name.to_owned(),
+            defined_below: self.vars[index + 1..].iter().any(|v| v.key == name),
+        });
+    }
+}
+
+/// Finds the byte offset of the brace closing a `${` expression whose contents start at the given
+/// offset, accounting for nested expressions within defaults.
+fn find_closing_brace(text: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text[start..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(start + i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+/// Consumes the characters of a bare `$VAR` reference name from the iterator
+fn take_name<'t>(text: &'t str, chars: &mut Peekable<CharIndices<'t>>) -> &'t str {
+    let start = chars.peek().map_or(text.len(), |(i, _)| *i);
+    let mut end = start;
+    while let Some((i, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_') {
+        end = i + c.len_utf8();
+    }
+    &text[start..end]
+}
+
+#[cfg(test)]
+mod interpolation_tests {
+    use super::*;
+    use crate::DotEnvFile;
+
+    /// Parses and expands the given input, returning the resulting key-value pairs
+    fn expand(input: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let mut dotenv = DotEnvFile::parse_from_str(input, false, false)?;
+        dotenv.expand(false, false)?;
+        Ok(dotenv
+            .iter()
+            .map(|v| (v.key.clone(), v.value.clone()))
+            .collect())
+    }
+
+    fn value_of(input: &str, key: &str) -> String {
+        expand(input)
+            .expect("failed to expand")
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .expect("key not found")
+            .1
+    }
+
+    #[test_case::test_case("USER=me\nURL=http://${USER}@host" => "http://me@host"; "expands braced reference")]
+    #[test_case::test_case("USER=me\nURL=http://$USER@host" => "http://me@host"; "expands bare reference")]
+    #[test_case::test_case("URL=http://${USER}@host\nUSER=me" => "http://@host"; "leaves forward reference unresolved")]
+    #[test_case::test_case("URL=${A}\nA=${URL}" => ""; "leaves references between variables unresolved in one direction")]
+    #[test_case::test_case("A=a\nB=${A}b\nURL=${B}c" => "abc"; "expands chained references")]
+    #[test_case::test_case("URL=${MISSING:-fallback}" => "fallback"; "uses default when unset")]
+    #[test_case::test_case("EMPTY=\nURL=${EMPTY:-fallback}" => "fallback"; "uses default when empty")]
+    #[test_case::test_case("A=a\nURL=${MISSING:-${A}}" => "a"; "expands references within default")]
+    #[test_case::test_case("A=a\nURL=${A:?must be set}" => "a"; "uses value when required reference is set")]
+    #[test_case::test_case("URL=${MISSING}x" => "x"; "replaces unresolved references with empty string")]
+    #[test_case::test_case("A=a\nURL='${A}'" => "${A}"; "keeps single quoted values literal")]
+    #[test_case::test_case("A=a\nURL=`${A}`" => "${A}"; "keeps backtick quoted values literal")]
+    #[test_case::test_case("A=a\nURL=\"${A} \\$A\"" => "a $A"; "keeps escaped dollar signs literal")]
+    #[test_case::test_case("URL=costs $5 or ${" => "costs $5 or ${"; "keeps dollar signs that are not references")]
+    #[test_case::test_case("A=first\nA=${A}-second\nURL=${A}" => "first-second"; "resolves against closest earlier definition")]
+    #[test_case::test_case("URL=${URL}" => ""; "does not resolve variable against itself")]
+    fn expands_references(input: &str) -> String {
+        value_of(input, "URL")
+    }
+
+    #[test]
+    fn reports_unresolved_references() {
+        let dotenv = DotEnvFile::parse_from_str("A=${MISSING}\nB=$OTHER\nC=$D\nD=d", false, false)
+            .expect("failed to parse");
+        let mut interpolator = Interpolator::new(&dotenv, false);
+        for index in 0..dotenv.len() {
+            interpolator.expand(index).expect("failed to expand");
+        }
+
+        assert_eq!(
+            interpolator
+                .unresolved
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "A references ${MISSING}, which is not defined",
+                "B references ${OTHER}, which is not defined",
+                "C references ${D}, which is only defined further down"
+            ]
+        );
+    }
+
+    #[test_case::test_case("A=${A}"; "braced")]
+    #[test_case::test_case("A=$A"; "bare")]
+    #[test_case::test_case("A=${A:-${A}}"; "within default")]
+    fn reports_self_references(input: &str) {
+        let mut dotenv = DotEnvFile::parse_from_str(input, false, false).expect("failed to parse");
+        dotenv.expand(false, false).expect("failed to expand");
+
+        assert_eq!(dotenv[0].value, "");
+        assert_eq!(
+            dotenv.warnings(),
+            ["A references itself, which is not defined above it"]
+        );
+    }
+
+    #[test_case::test_case("A=${MISSING:?must be set}" => matches Err(e) if e.to_string().contains("MISSING: must be set"); "fails on unresolved required reference")]
+    #[test_case::test_case("A=${MISSING:?}" => matches Err(e) if e.to_string().contains("parameter null or not set"); "fails on unresolved required reference without message")]
+    fn fails_to_expand(input: &str) -> anyhow::Result<Vec<(String, String)>> {
+        expand(input)
+    }
+
+    #[test]
+    fn expands_from_process_env_when_enabled() {
+        let dotenv = DotEnvFile::parse_from_str("A=${CARGO_PKG_NAME}", false, false)
+            .expect("failed to parse");
+
+        let mut interpolator = Interpolator::new(&dotenv, true);
+        assert_eq!(interpolator.expand(0).expect("failed to expand"), "env2bws");
+
+        let mut interpolator = Interpolator::new(&dotenv, false);
+        assert_eq!(interpolator.expand(0).expect("failed to expand"), "");
+    }
+}
@@ -1,11 +1,26 @@
 #![doc = include_str!("../README.md")]
-pub use dotenv::DotEnvFile;
-pub use env_var::EnvVar;
-pub use import_payload::{ImportPayload, Project, ProjectAssignment, Secret};
-
+pub use annotation::Annotations;
+pub use diff::{PayloadDiff, ProjectDiff, SecretChange};
+pub use dotenv::{DotEnvFile, DuplicateKey, DuplicatePolicy, ParseOptions};
+pub use env_var::{EnvVar, Quote};
+pub use error::{ParseError, ParseErrorKind};
+pub use import_payload::{ImportPayload, OutputOptions, Project, ProjectAssignment, Secret};
+pub use project_listing::ProjectListing;
+pub use project_mapping::FileProjectMapping;
+pub use routing::{KeyPattern, ProjectTarget, RoutingRule, RoutingRules};
+pub use validation::{PayloadIssue, ValidationPolicy};
+
+pub mod annotation;
+pub mod diff;
 pub mod dotenv;
 pub mod env_var;
+pub mod error;
 pub mod import_payload;
+mod interpolation;
+pub mod project_listing;
+pub mod project_mapping;
+pub mod routing;
+pub mod validation;
 
 #[cfg(test)]
 mod test_sample {
@@ -19,7 +34,7 @@ mod test_sample {
             .expect("could not parse file");
 
         let expected_output = include_str!("../sample.json");
-        let expected_payload = serde_json::from_str::<ImportPayload>(&expected_output)
+        let expected_payload = serde_json::from_str::<ImportPayload>(expected_output)
             .expect("could not deserialize expected JSON");
         let import_payload = ImportPayload::from_dotenv(dotenv, ProjectAssignment::None);
 
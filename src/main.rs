@@ -1,10 +1,15 @@
 use anyhow::anyhow;
 use clap::Parser;
-use cli::Cli;
-use env2bws::{DotEnvFile, ImportPayload, ProjectAssignment};
+use cli::{Bws2envArgs, Cli, Command, DiffArgs, MergeArgs, ValidateArgs};
+use env2bws::{
+    dotenv::STDIN_PATH, import_payload::DEFAULT_ID_NAMESPACE, DotEnvFile, FileProjectMapping,
+    ImportPayload, KeyPattern, OutputOptions, ParseOptions, PayloadDiff, PayloadIssue,
+    ProjectAssignment, ProjectListing, ProjectTarget, RoutingRules, SecretChange,
+};
 use std::{
     fs::OpenOptions,
     io::{self, Write},
+    path::{Path, PathBuf},
 };
 
 /// Module used for handling CLI behaviour with [`clap`]
@@ -12,10 +17,15 @@ use std::{
 /// Defined in this main module so as to not expose via public API
 mod cli {
     use clap::{
-        builder::{styling::AnsiColor, Styles},
-        Args, Parser,
+        builder::{styling::AnsiColor, Styles, TypedValueParser},
+        error::ErrorKind,
+        ArgGroup, Args, Parser, Subcommand, ValueEnum,
+    };
+    use env2bws::{
+        dotenv::DEFAULT_SECTION_PATTERN, project_mapping::DEFAULT_PROJECT_NAME_TEMPLATE,
+        DuplicatePolicy, KeyPattern, ProjectTarget, RoutingRule, ValidationPolicy,
     };
-    use std::path::PathBuf;
+    use std::{ffi::OsStr, path::PathBuf};
 
     /// Styling used for help output
     pub(crate) const STYLES: Styles = Styles::styled()
@@ -28,23 +38,148 @@ mod cli {
     /// Parse the given .env file and output in a JSON format that is compatible with Bitwarden Secrets
     /// Manager's import feature.
     #[derive(Debug, Clone, Parser, PartialEq, Eq)]
-    #[command(styles = STYLES, arg_required_else_help = true)]
+    #[command(
+        styles = STYLES,
+        arg_required_else_help = true,
+        args_conflicts_with_subcommands = true,
+        subcommand_negates_reqs = true,
+        group = ArgGroup::new("id_derivation").multiple(true)
+    )]
     pub(crate) struct Cli {
-        /// Path to the .env file to parse
+        #[command(subcommand)]
+        pub(crate) command: Option<Command>,
+
+        /// Paths to the .env files to parse, or "-" to read from stdin
         ///
-        /// Note: The file must be in the format of a .env file, with each line containing a key-value
-        /// pair separated by an equals sign (and followed by an optional comment). For example:
+        /// Glob patterns (e.g. "services/*/.env") are expanded. When more than one file is given,
+        /// the files are merged in order, with variables in later files overriding variables of the
+        /// same key in earlier files (e.g. `.env .env.production .env.local`).
+        ///
+        /// Note: Each file must be in the format of a .env file, with each line containing a key-value
+        /// pair separated by an equals sign (and followed by an optional comment). Quoted values may
+        /// span multiple lines. For example:
         ///
         /// SECRET_VALUE_1=12345
         /// SECRET_VALUE_2=abcde  # Optional comment
+        /// SECRET_VALUE_3="first line
+        /// second line"
         ///
-        /// The file may have any name as long as it follows this format.
-        #[arg(verbatim_doc_comment)]
-        pub(crate) dotenv_path: PathBuf,
+        /// The files may have any name as long as they follow this format.
+        #[arg(
+            verbatim_doc_comment,
+            required_unless_present = "from_env",
+            value_parser = InputPathParser
+        )]
+        pub(crate) dotenv_paths: Vec<PathBuf>,
+
+        /// Capture the variables of the current process environment, rather than parsing .env files
+        ///
+        /// Useful for snapshotting the environment of a CI job or container. Every variable is
+        /// captured unless --env-prefix, --env-var or --env-pattern is given, in which case only the
+        /// variables matching any of them are.
+        #[arg(long, conflicts_with_all = ["dotenv_paths", "project_per_file"])]
+        pub(crate) from_env: bool,
+
+        /// Capture only environment variables whose key starts with the given prefix, e.g. "APP_"
+        ///
+        /// Requires --from-env option
+        #[arg(
+            long,
+            value_name = "PREFIX",
+            requires = "from_env",
+            conflicts_with = "dotenv_paths"
+        )]
+        pub(crate) env_prefix: Vec<String>,
+
+        /// Capture the environment variable with the given key
+        ///
+        /// Requires --from-env option
+        #[arg(
+            long,
+            value_name = "KEY",
+            requires = "from_env",
+            conflicts_with = "dotenv_paths"
+        )]
+        pub(crate) env_var: Vec<String>,
+
+        /// Capture only environment variables whose key matches the given regular expression
+        ///
+        /// Requires --from-env option
+        #[arg(
+            long,
+            value_name = "REGEX",
+            requires = "from_env",
+            conflicts_with = "dotenv_paths",
+            value_parser = |s: &str| KeyPattern::regex(s)
+        )]
+        pub(crate) env_pattern: Vec<KeyPattern>,
 
         #[command(flatten)]
         pub(crate) project_assignment: ProjectAssignmentArgs,
 
+        /// Template used to name the project of each input file
+        ///
+        /// The placeholders {path} (path of the file), {dir} (name of the directory containing the
+        /// file), {file} (name of the file) and {env} (the environment named by the file, e.g.
+        /// "production" for .env.production or production.env) are filled in for each file.
+        ///
+        /// Requires --project-per-file option
+        #[arg(long, requires = "project_per_file", default_value = DEFAULT_PROJECT_NAME_TEMPLATE)]
+        pub(crate) project_name_template: String,
+
+        /// Path to a JSON file mapping project names to the IDs of existing projects, e.g.
+        /// {"api-production": "<project-id>"}
+        ///
+        /// Files whose project name is listed are assigned to the existing project instead of a new
+        /// one.
+        ///
+        /// Requires --project-per-file option
+        #[arg(long, requires = "project_per_file")]
+        pub(crate) project_map: Option<PathBuf>,
+
+        /// Path to a JSON file listing the existing projects, against which --project names are
+        /// resolved
+        ///
+        /// Either the output of `bws project list --output json`, or a previous Bitwarden Secrets
+        /// Manager export, may be used.
+        ///
+        /// Requires --project option
+        #[arg(long, requires = "project")]
+        pub(crate) projects_file: Option<PathBuf>,
+
+        /// Define a new project for each --project name that isn't found in --projects-file, rather
+        /// than failing
+        ///
+        /// Requires --project option
+        #[arg(long, requires = "project")]
+        pub(crate) create_missing_projects: bool,
+
+        /// Regular expression matching the header comments that start a new section
+        ///
+        /// The section is named after the "name" capture group, or the first capture group if there
+        /// is no such group. A header with an empty name ends the current section. For example, use
+        /// "^# (Service \d+)" to start a section at each "# Service 1" comment.
+        ///
+        /// Requires --project-per-section option
+        #[arg(long, requires = "project_per_section", default_value = DEFAULT_SECTION_PATTERN)]
+        pub(crate) section_pattern: String,
+
+        /// The ID of an existing project, or the name of a new project, to which secrets matching
+        /// no routing rule are assigned
+        ///
+        /// Takes precedence over any fallback in --routes-file.
+        ///
+        /// Requires --route or --routes-file option
+        #[arg(long, value_name = "PROJECT", requires = "routing")]
+        pub(crate) route_fallback: Option<ProjectTarget>,
+
+        /// Assign each secret to the projects of every routing rule matching its key, rather than
+        /// only the first, allowing secrets to be assigned to several projects
+        ///
+        /// Requires --route or --routes-file option
+        #[arg(long, requires = "routing")]
+        pub(crate) match_all_routes: bool,
+
         /// Output file path
         ///
         /// If not provided, the output will be printed to stdout.
@@ -55,13 +190,100 @@ mod cli {
         ///
         /// If a comment exists above the line, it takes precedence over any comment that is inline with
         /// the variable. In order for comments to be associated with a variable defined under it, there
-        /// must be no whitespace between the comment line and the variable declaration line
+        /// must be no blank lines between the comment line and the variable declaration line. Multiple
+        /// consecutive comment lines are joined into a single note
         #[arg(short = 'c', long)]
         pub(crate) parse_comments: bool,
 
+        /// Interpret annotations within comments, which control how each secret is imported
+        ///
+        /// Annotations are written inline as @name or @name=value, e.g.
+        /// API_KEY=abc # @project=payments @note="Stripe live key"
+        /// or without the @ on a comment line starting with @bws directly above a variable, e.g.
+        /// # @bws project=payments skip
+        ///
+        /// Supported annotations are project=PROJECT (the ID of an existing project or the name of
+        /// a new project, in place of any other project assignment), note=TEXT (in place of any
+        /// comment), key=KEY (to rename the secret) and skip (to leave the variable out).
+        #[arg(short, long, verbatim_doc_comment)]
+        pub(crate) annotations: bool,
+
+        /// Fail on the first line that cannot be parsed, rather than warning about it
+        ///
+        /// Without this option, lines that cannot be parsed are reported as warnings on stderr and
+        /// either skipped (if they contain no assignment) or have their value kept as written (if
+        /// their value is improperly quoted).
+        #[arg(short, long)]
+        pub(crate) strict: bool,
+
+        /// Expand $VAR and ${VAR} references within values
+        ///
+        /// References are resolved against the variables defined above them in the file. The
+        /// ${VAR:-default} and ${VAR:?error} forms are supported. Values wrapped in single quotes or
+        /// backticks are never expanded.
+        #[arg(short, long)]
+        pub(crate) expand: bool,
+
+        /// When expanding references, resolve variables not defined above them against the
+        /// environment of the current process
+        ///
+        /// Requires -e/--expand option
+        #[arg(long, requires = "expand")]
+        pub(crate) expand_from_env: bool,
+
+        /// How to handle keys that aren't valid environment variable names, and keys, values or notes
+        /// that exceed the length limits of Bitwarden Secrets Manager
+        #[arg(long, value_enum, default_value_t)]
+        pub(crate) validation: ValidationPolicyArg,
+
+        /// How to handle keys that are defined more than once
+        ///
+        /// Every duplicated key is reported as a warning on stderr, along with the lines it is defined on.
+        #[arg(short, long, value_enum, default_value_t)]
+        pub(crate) duplicates: DuplicatePolicyArg,
+
+        /// Derive the IDs of new projects and secrets from their names and keys, rather than
+        /// generating random IDs
+        ///
+        /// Converting the same input again then produces the same output, so that it can be
+        /// committed and reviewed.
+        #[arg(long, group = "id_derivation")]
+        pub(crate) deterministic_ids: bool,
+
+        /// Seed from which deterministic IDs are derived, so that different seeds produce different
+        /// IDs for the same input
+        ///
+        /// Requires --deterministic-ids or --canonical option
+        #[arg(long, requires = "id_derivation")]
+        pub(crate) id_seed: Option<String>,
+
+        /// Sort secrets by key in the output, rather than keeping the order of the input
+        #[arg(long)]
+        pub(crate) sort_secrets: bool,
+
+        /// Sort projects by name in the output, rather than keeping the order of the input
+        #[arg(long)]
+        pub(crate) sort_projects: bool,
+
+        /// Write the JSON output on a single line, rather than indented over several lines
+        #[arg(long)]
+        pub(crate) compact: bool,
+
+        /// Don't end the output with a newline
+        #[arg(long)]
+        pub(crate) no_trailing_newline: bool,
+
+        /// Produce the exact same output for the same variables and projects, regardless of the
+        /// order they're given in
+        ///
+        /// Implies --deterministic-ids, --sort-secrets and --sort-projects.
+        #[arg(long, group = "id_derivation")]
+        pub(crate) canonical: bool,
+
         /// Enable verbose output
         ///
-        /// All verbose logging is written to stderr so that it doesn't interfere with the ability to
+        /// This includes the file and line that the final value of each secret came from. All
+        /// verbose logging is written to stderr so that it doesn't interfere with the ability to
         /// pipe or redirect processed JSON output from stdout.
         #[arg(short, long)]
         pub(crate) verbose: bool,
@@ -73,22 +295,334 @@ mod cli {
         pub(crate) force_overwrite: bool,
     }
 
-    /// An [`ArgGroup`][clap::ArgGroup] that is used to determine which project a secret should be
-    /// assigned to.
+    /// Additional operations, used in place of the default conversion from a .env file
+    #[derive(Debug, Clone, Subcommand, PartialEq, Eq)]
+    pub(crate) enum Command {
+        /// Convert a Bitwarden Secrets Manager import or export JSON file back into a .env file
+        Bws2env(Bws2envArgs),
+        /// Check a Bitwarden Secrets Manager import JSON file for problems before importing it
+        Validate(ValidateArgs),
+        /// Compare a .env file with the secrets of a Bitwarden Secrets Manager export JSON file
+        Diff(DiffArgs),
+        /// Combine several Bitwarden Secrets Manager import JSON files into one
+        Merge(MergeArgs),
+    }
+
+    /// Arguments for the `bws2env` subcommand
+    #[derive(Debug, Clone, Args, PartialEq, Eq)]
+    pub(crate) struct Bws2envArgs {
+        /// Path to the JSON file to convert
+        pub(crate) json_path: PathBuf,
+
+        #[command(flatten)]
+        pub(crate) project_filter: ProjectFilterArgs,
+
+        /// Output file path
+        ///
+        /// If not provided, the output will be printed to stdout.
+        #[arg(short, long)]
+        pub(crate) output_file: Option<PathBuf>,
+
+        /// If the chosen output file already exists, force it to be overwritten
+        ///
+        /// Requires -o/--output-file option
+        #[arg(short, long, requires = "output_file")]
+        pub(crate) force_overwrite: bool,
+    }
+
+    /// Arguments for the `validate` subcommand
+    #[derive(Debug, Clone, Args, PartialEq, Eq)]
+    pub(crate) struct ValidateArgs {
+        /// Path to the JSON file to check
+        ///
+        /// Secrets must not share IDs, nor keys within the same project or among unassigned
+        /// secrets, and their keys, values and notes must fit within the length limits of Bitwarden
+        /// Secrets Manager. Projects that secrets are assigned to, but that aren't defined in the
+        /// file, are reported as existing projects unless --projects-file or --project-id is given.
+        pub(crate) json_path: PathBuf,
+
+        /// Path to a JSON file listing the existing projects, such as the output of
+        /// `bws project list --output json`
+        ///
+        /// Secrets assigned to any project that is neither defined in the file nor listed are
+        /// reported as problems.
+        #[arg(long)]
+        pub(crate) projects_file: Option<PathBuf>,
+
+        /// ID of an existing project that secrets may be assigned to
+        ///
+        /// May be given more than once. Secrets assigned to any project that is neither defined in
+        /// the file nor given are reported as problems.
+        #[arg(short, long)]
+        pub(crate) project_id: Vec<uuid::Uuid>,
+    }
+
+    /// Arguments for the `diff` subcommand
+    #[derive(Debug, Clone, Args, PartialEq, Eq)]
+    pub(crate) struct DiffArgs {
+        /// Path to the .env file holding the wanted secrets, or "-" to read from stdin
+        pub(crate) dotenv_path: PathBuf,
+
+        /// Path to the export JSON file holding the existing secrets
+        pub(crate) export_path: PathBuf,
+
+        /// Compare the variables with the secrets of the project having the given ID
+        ///
+        /// Without --project-id or --project-name, the variables are compared with the secrets that
+        /// aren't assigned to any project. Conflicts with --project-name.
+        #[arg(short, long, conflicts_with = "project_name")]
+        pub(crate) project_id: Option<uuid::Uuid>,
+
+        /// Compare the variables with the secrets of the project having the given name
+        ///
+        /// If no such project exists, all variables are reported as added to a new project of that
+        /// name. Conflicts with --project-id.
+        #[arg(short = 'n', long)]
+        pub(crate) project_name: Option<String>,
+
+        /// Parse comments as notes, and compare them with the notes of existing secrets
+        #[arg(short = 'c', long)]
+        pub(crate) parse_comments: bool,
+
+        /// Show secret values in the comparison, rather than masking them
+        #[arg(long)]
+        pub(crate) show_values: bool,
+
+        /// Write an import JSON file holding only the new and changed secrets to the given path
+        ///
+        /// Changed secrets keep the IDs of the secrets they replace.
+        #[arg(short, long)]
+        pub(crate) output_file: Option<PathBuf>,
+
+        /// If the chosen output file already exists, force it to be overwritten
+        ///
+        /// Requires -o/--output-file option
+        #[arg(short, long, requires = "output_file")]
+        pub(crate) force_overwrite: bool,
+    }
+
+    /// Arguments for the `merge` subcommand
+    #[derive(Debug, Clone, Args, PartialEq, Eq)]
+    pub(crate) struct MergeArgs {
+        /// Paths to the JSON files to merge, in order
+        ///
+        /// Glob patterns (e.g. "teams/*/import.json") are expanded. Projects with the same ID or
+        /// name are merged into one.
+        #[arg(required = true)]
+        pub(crate) json_paths: Vec<PathBuf>,
+
+        /// How to handle secrets with the same key in the same project
+        ///
+        /// Secrets with identical values and notes are always merged into one.
+        #[arg(long, value_enum, default_value_t = DuplicatePolicyArg::Error)]
+        pub(crate) conflicts: DuplicatePolicyArg,
+
+        /// Output file path
+        ///
+        /// If not provided, the output will be printed to stdout.
+        #[arg(short, long)]
+        pub(crate) output_file: Option<PathBuf>,
+
+        /// Enable verbose output
+        #[arg(short, long)]
+        pub(crate) verbose: bool,
+
+        /// If the chosen output file already exists, force it to be overwritten
+        ///
+        /// Requires -o/--output-file option
+        #[arg(short, long, requires = "output_file")]
+        pub(crate) force_overwrite: bool,
+    }
+
+    /// An [`ArgGroup`][clap::ArgGroup] that is used to limit conversion to the secrets of a single
+    /// project.
     #[derive(Debug, Clone, Args, PartialEq, Eq)]
     #[group(required = false, multiple = false)]
-    pub(crate) struct ProjectAssignmentArgs {
-        /// Assign all parsed secrets to an existing project having the given ID.
+    pub(crate) struct ProjectFilterArgs {
+        /// Only convert secrets assigned to the project having the given ID.
         ///
-        /// Conflicts with --new-project-name.
+        /// Conflicts with --project-name.
         #[arg(short, long)]
         pub(crate) project_id: Option<uuid::Uuid>,
 
-        /// Define new project with the given name, to which all secrets will be assigned.
+        /// Only convert secrets assigned to the project having the given name.
         ///
         /// Conflicts with --project-id.
         #[arg(short = 'n', long)]
-        pub(crate) new_project_name: Option<String>,
+        pub(crate) project_name: Option<String>,
+    }
+
+    /// An [`ArgGroup`][clap::ArgGroup] that is used to determine which project a secret should be
+    /// assigned to.
+    #[derive(Debug, Clone, Args, PartialEq, Eq)]
+    #[group(required = false, multiple = true)]
+    pub(crate) struct ProjectAssignmentArgs {
+        /// Assign all parsed secrets to an existing project having the given ID.
+        ///
+        /// May be given more than once, and combined with --new-project-name, to assign all secrets
+        /// to several projects.
+        #[arg(
+            short,
+            long,
+            conflicts_with_all = ["project_per_file", "project_per_section", "routing"]
+        )]
+        pub(crate) project_id: Vec<uuid::Uuid>,
+
+        /// Define new project with the given name, to which all secrets will be assigned.
+        ///
+        /// May be given more than once, and combined with --project-id, to assign all secrets to
+        /// several projects.
+        #[arg(
+            short = 'n',
+            long,
+            conflicts_with_all = ["project_per_file", "project_per_section", "routing"]
+        )]
+        pub(crate) new_project_name: Vec<String>,
+
+        /// Assign all parsed secrets to the existing project having the given name, as found in
+        /// --projects-file.
+        ///
+        /// Fails if no project, or more than one project, has the given name, unless
+        /// --create-missing-projects is given. May be given more than once, and combined with
+        /// --project-id and --new-project-name, to assign all secrets to several projects.
+        #[arg(
+            long,
+            value_name = "NAME",
+            requires = "projects_file",
+            conflicts_with_all = ["project_per_file", "project_per_section", "routing"]
+        )]
+        pub(crate) project: Vec<String>,
+
+        /// Assign the secrets of each input file to a project of its own, rather than merging the
+        /// files together.
+        ///
+        /// Projects are named using --project-name-template, and are created unless they're listed
+        /// in --project-map.
+        ///
+        /// Conflicts with --project-id and --new-project-name.
+        #[arg(long, conflicts_with_all = ["project_per_section", "routing"])]
+        pub(crate) project_per_file: bool,
+
+        /// Define a new project for each section of the input, to which the secrets declared in that
+        /// section will be assigned.
+        ///
+        /// Sections start at header comments matching --section-pattern, such as
+        /// "# [project: My Project]". Secrets declared before the first section are left unassigned.
+        ///
+        /// Conflicts with --project-id, --new-project-name and --project-per-file.
+        #[arg(long, conflicts_with = "routing")]
+        pub(crate) project_per_section: bool,
+
+        /// Assign secrets to projects according to a rule of the form PATTERN=PROJECT, where
+        /// PATTERN is a glob matched against the whole key (e.g. "SERVICE_1_*"), and PROJECT is the
+        /// ID of an existing project or the name of a new project.
+        ///
+        /// May be given more than once, in which case each secret is assigned by the first rule
+        /// matching its key (or by every rule matching its key, with --match-all-routes). Secrets
+        /// matching no rule are left unassigned, unless --route-fallback is given.
+        ///
+        /// Conflicts with --project-id, --new-project-name, --project-per-file,
+        /// --project-per-section and --routes-file.
+        #[arg(
+            long = "route",
+            value_name = "PATTERN=PROJECT",
+            group = "routing",
+            conflicts_with = "routes_file"
+        )]
+        pub(crate) routes: Vec<RoutingRule>,
+
+        /// Assign secrets to projects according to the rules in the given JSON file, e.g.
+        /// {"rules": [{"glob": "SERVICE_1_*", "newProject": "Service 1"}, {"regex": "^SERVICE_2_",
+        /// "projectId": "<project-id>"}], "fallback": {"newProject": "Shared"}}
+        ///
+        /// Each secret is assigned by the first rule matching its key (or by every rule matching its
+        /// key, if "matchAll" is true), or else by the optional fallback.
+        ///
+        /// Conflicts with --project-id, --new-project-name, --project-per-file,
+        /// --project-per-section and --route.
+        #[arg(long, group = "routing")]
+        pub(crate) routes_file: Option<PathBuf>,
+    }
+
+    /// Parses input paths, rejecting the name of a subcommand given after other input paths
+    ///
+    /// Once an input path has been given, clap consumes every following value as another input
+    /// path, so `env2bws .env bws2env` would otherwise try to read a file named "bws2env". A file
+    /// that actually has the name of a subcommand can still be given, as long as it exists.
+    #[derive(Debug, Clone, Copy)]
+    struct InputPathParser;
+
+    impl TypedValueParser for InputPathParser {
+        type Value = PathBuf;
+
+        fn parse_ref(
+            &self,
+            cmd: &clap::Command,
+            _arg: Option<&clap::Arg>,
+            value: &OsStr,
+        ) -> Result<Self::Value, clap::Error> {
+            let path = PathBuf::from(value);
+            let is_subcommand = cmd.get_subcommands().any(|sub| sub.get_name() == value);
+            if is_subcommand && !path.exists() {
+                let message = format!(
+                    "the subcommand '{}' cannot be used with input files\n",
+                    value.to_string_lossy()
+                );
+                return Err(clap::Error::raw(ErrorKind::ArgumentConflict, message).with_cmd(cmd));
+            }
+            Ok(path)
+        }
+    }
+
+    /// Mirrors [`ValidationPolicy`] so that it can be chosen on the command line
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+    pub(crate) enum ValidationPolicyArg {
+        /// Don't check variables
+        #[default]
+        Off,
+        /// Fail if any problem is found
+        Error,
+        /// Report each problem as a warning
+        Warn,
+        /// Rename invalid keys, truncate long notes and drop variables without a key, failing on
+        /// any problem that can't be fixed
+        Fix,
+    }
+
+    impl From<ValidationPolicyArg> for ValidationPolicy {
+        fn from(value: ValidationPolicyArg) -> Self {
+            match value {
+                ValidationPolicyArg::Off => ValidationPolicy::Off,
+                ValidationPolicyArg::Error => ValidationPolicy::Error,
+                ValidationPolicyArg::Warn => ValidationPolicy::Warn,
+                ValidationPolicyArg::Fix => ValidationPolicy::Fix,
+            }
+        }
+    }
+
+    /// Mirrors [`DuplicatePolicy`] so that it can be chosen on the command line
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+    pub(crate) enum DuplicatePolicyArg {
+        /// Fail if any key is defined more than once
+        Error,
+        /// Keep only the first definition of each key
+        KeepFirst,
+        /// Keep only the last definition of each key, the way a shell would
+        KeepLast,
+        /// Keep every definition of each key
+        #[default]
+        KeepAll,
+    }
+
+    impl From<DuplicatePolicyArg> for DuplicatePolicy {
+        fn from(value: DuplicatePolicyArg) -> Self {
+            match value {
+                DuplicatePolicyArg::Error => DuplicatePolicy::Error,
+                DuplicatePolicyArg::KeepFirst => DuplicatePolicy::KeepFirst,
+                DuplicatePolicyArg::KeepLast => DuplicatePolicy::KeepLast,
+                DuplicatePolicyArg::KeepAll => DuplicatePolicy::KeepAll,
+            }
+        }
     }
 
     #[cfg(test)]
@@ -107,7 +641,61 @@ mod cli {
         #[test_case::test_case(&mut [".env", "--output-file", "out.json", "--verbose", "--parse-comments"] => matches Ok(_); "happy path no project")]
         #[test_case::test_case(&mut [".env", "--output-file", "out.json", "--new-project-name", "my-new-project", "--verbose", "--parse-comments"] => matches Ok(_); "happy path new project")]
         #[test_case::test_case(&mut [".env", "--project-id", &uuid::Uuid::new_v4().to_string(), "--output-file", "out.json", "--verbose", "--parse-comments"] => matches Ok(_); "happy path existing project")]
-        #[test_case::test_case(&mut [".env", "--project-id", &uuid::Uuid::new_v4().to_string(), "--new-project-name", "my-new-project"] => matches Err(ErrorKind::ArgumentConflict); "fails when conflicting new/existing project")]
+        #[test_case::test_case(&mut [".env", "--project-id", &uuid::Uuid::new_v4().to_string(), "--new-project-name", "my-new-project", "-n", "other"] => matches Ok(Cli { project_assignment: ProjectAssignmentArgs { project_id, new_project_name, .. }, .. }) if project_id.len() == 1 && new_project_name.len() == 2; "happy path multiple projects")]
+        #[test_case::test_case(&mut [".env", "--project", "Payments", "--project", "Billing", "--projects-file", "projects.json", "--create-missing-projects"] => matches Ok(Cli { project_assignment: ProjectAssignmentArgs { project, .. }, create_missing_projects: true, .. }) if project.len() == 2; "happy path projects by name")]
+        #[test_case::test_case(&mut [".env", "--project", "Payments"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when naming projects without projects file")]
+        #[test_case::test_case(&mut [".env", "--projects-file", "projects.json"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when listing projects without project names")]
+        #[test_case::test_case(&mut [".env", "--project", "Payments", "--projects-file", "projects.json", "--project-per-section"] => matches Err(ErrorKind::ArgumentConflict); "fails when conflicting project name and project per section")]
+        #[test_case::test_case(&mut [".env", "--route", "A*=a", "--match-all-routes"] => matches Ok(Cli { match_all_routes: true, .. }); "happy path matching all routes")]
+        #[test_case::test_case(&mut [".env", "--match-all-routes"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when matching all routes without routing rules")]
+        #[test_case::test_case(&mut [".env", "--project-id", &uuid::Uuid::new_v4().to_string(), "--project-per-file"] => matches Err(ErrorKind::ArgumentConflict); "fails when conflicting existing project and project per file")]
+        #[test_case::test_case(&mut ["-", ".env.local"] => matches Ok(Cli { dotenv_paths, .. }) if dotenv_paths[0].as_os_str() == "-"; "happy path stdin")]
+        #[test_case::test_case(&mut ["--from-env", "--env-prefix", "APP_", "--env-var", "HOME", "--env-pattern", "^CI_"] => matches Ok(Cli { from_env: true, env_prefix, env_var, env_pattern, .. }) if env_prefix.len() == 1 && env_var.len() == 1 && env_pattern.len() == 1; "happy path from environment")]
+        #[test_case::test_case(&mut ["--from-env", "--env-pattern", "("] => matches Err(ErrorKind::ValueValidation); "fails on invalid environment pattern")]
+        #[test_case::test_case(&mut [".env", "--from-env"] => matches Err(ErrorKind::ArgumentConflict); "fails when conflicting files and environment")]
+        #[test_case::test_case(&mut ["--env-prefix", "APP_"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when filtering environment without from env")]
+        #[test_case::test_case(&mut [".env", "--env-var", "HOME"] => matches Err(ErrorKind::ArgumentConflict); "fails when filtering environment with files")]
+        #[test_case::test_case(&mut [".env", ".env.local", "services/*/.env"] => matches Ok(Cli { dotenv_paths, .. }) if dotenv_paths.len() == 3; "happy path multiple files")]
+        #[test_case::test_case(&mut [".env", ".env.local", "--project-per-file"] => matches Ok(Cli { project_assignment: ProjectAssignmentArgs { project_per_file: true, .. }, .. }); "happy path project per file")]
+        #[test_case::test_case(&mut ["services/*/.env", "--project-per-file", "--project-name-template", "{dir}-{env}", "--project-map", "projects.json"] => matches Ok(Cli { project_map: Some(_), .. }); "happy path project mapping")]
+        #[test_case::test_case(&mut [".env", "--project-per-file", "--new-project-name", "my-new-project"] => matches Err(ErrorKind::ArgumentConflict); "fails when conflicting project per file")]
+        #[test_case::test_case(&mut [".env", "--project-name-template", "{dir}"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when templating without project per file")]
+        #[test_case::test_case(&mut [".env", "--project-per-section", "--section-pattern", "^# (Service \\d+)"] => matches Ok(Cli { project_assignment: ProjectAssignmentArgs { project_per_section: true, .. }, .. }); "happy path project per section")]
+        #[test_case::test_case(&mut [".env", "--project-per-section", "--project-per-file"] => matches Err(ErrorKind::ArgumentConflict); "fails when conflicting project per section")]
+        #[test_case::test_case(&mut [".env", "--section-pattern", "^# (.+)"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when matching sections without project per section")]
+        #[test_case::test_case(&mut [".env", "--route", "SERVICE_1_*=Service 1", "--route", &format!("SERVICE_2_*={}", uuid::Uuid::new_v4()), "--route-fallback", "Shared"] => matches Ok(Cli { project_assignment: ProjectAssignmentArgs { routes, .. }, route_fallback: Some(ProjectTarget::NewProject(_)), .. }) if routes.len() == 2; "happy path routing rules")]
+        #[test_case::test_case(&mut [".env", "--routes-file", "routes.json", "--route-fallback", &uuid::Uuid::new_v4().to_string()] => matches Ok(Cli { route_fallback: Some(ProjectTarget::ProjectId(_)), .. }); "happy path routing rules file")]
+        #[test_case::test_case(&mut [".env", "--route", "SERVICE_1_*"] => matches Err(ErrorKind::ValueValidation); "fails on invalid routing rule")]
+        #[test_case::test_case(&mut [".env", "--route", "A*=a", "--routes-file", "routes.json"] => matches Err(ErrorKind::ArgumentConflict); "fails when conflicting routing rules")]
+        #[test_case::test_case(&mut [".env", "--route", "A*=a", "--new-project-name", "b"] => matches Err(ErrorKind::ArgumentConflict); "fails when conflicting routing rules and project")]
+        #[test_case::test_case(&mut [".env", "--route-fallback", "Shared"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when falling back without routing rules")]
+        #[test_case::test_case(&mut [".env", "-a"] => matches Ok(Cli { annotations: true, .. }); "happy path annotations")]
+        #[test_case::test_case(&mut [".env", "--strict"] => matches Ok(Cli { strict: true, .. }); "happy path strict")]
+        #[test_case::test_case(&mut [".env"] => matches Ok(Cli { validation: ValidationPolicyArg::Off, .. }); "skips validation by default")]
+        #[test_case::test_case(&mut [".env", "--validation", "fix"] => matches Ok(Cli { validation: ValidationPolicyArg::Fix, .. }); "happy path validation policy")]
+        #[test_case::test_case(&mut [".env", "--validation", "ignore"] => matches Err(ErrorKind::InvalidValue); "fails on unknown validation policy")]
+        #[test_case::test_case(&mut [".env", "--duplicates", "keep-last"] => matches Ok(Cli { duplicates: DuplicatePolicyArg::KeepLast, .. }); "happy path duplicate policy")]
+        #[test_case::test_case(&mut [".env", "--deterministic-ids", "--id-seed", "staging"] => matches Ok(Cli { deterministic_ids: true, id_seed: Some(_), .. }); "happy path deterministic ids")]
+        #[test_case::test_case(&mut [".env", "--id-seed", "staging"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when seeding without deterministic ids")]
+        #[test_case::test_case(&mut [".env", "--sort-secrets", "--sort-projects", "--compact", "--no-trailing-newline"] => matches Ok(Cli { sort_secrets: true, sort_projects: true, compact: true, no_trailing_newline: true, .. }); "happy path output formatting")]
+        #[test_case::test_case(&mut [".env", "--canonical", "--id-seed", "staging"] => matches Ok(Cli { canonical: true, id_seed: Some(_), .. }); "happy path canonical output")]
+        #[test_case::test_case(&mut [".env", "--expand", "--expand-from-env"] => matches Ok(Cli { expand: true, expand_from_env: true, .. }); "happy path expansion")]
+        #[test_case::test_case(&mut [".env", "--expand-from-env"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when expanding from env without expansion")]
+        #[test_case::test_case(&mut ["bws2env", "export.json"] => matches Ok(Cli { command: Some(Command::Bws2env(_)), .. }); "happy path bws2env")]
+        #[test_case::test_case(&mut ["bws2env", "export.json", "--project-name", "my-project", "--output-file", ".env", "--force-overwrite"] => matches Ok(Cli { command: Some(Command::Bws2env(Bws2envArgs { project_filter: ProjectFilterArgs { project_name: Some(_), .. }, .. })), .. }); "happy path bws2env with project")]
+        #[test_case::test_case(&mut ["bws2env", "export.json", "--project-id", &uuid::Uuid::new_v4().to_string(), "--project-name", "my-project"] => matches Err(ErrorKind::ArgumentConflict); "fails when conflicting project filters")]
+        #[test_case::test_case(&mut ["bws2env"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when bws2env has no input")]
+        #[test_case::test_case(&mut ["validate", "import.json"] => matches Ok(Cli { command: Some(Command::Validate(_)), .. }); "happy path validate")]
+        #[test_case::test_case(&mut ["validate", "import.json", "--projects-file", "projects.json", "-p", &uuid::Uuid::new_v4().to_string()] => matches Ok(Cli { command: Some(Command::Validate(ValidateArgs { projects_file: Some(_), project_id, .. })), .. }) if project_id.len() == 1; "happy path validate with existing projects")]
+        #[test_case::test_case(&mut ["validate"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when validate has no input")]
+        #[test_case::test_case(&mut ["diff", ".env", "export.json", "-n", "my-project", "-c", "--show-values", "-o", "changes.json"] => matches Ok(Cli { command: Some(Command::Diff(DiffArgs { project_name: Some(_), parse_comments: true, show_values: true, output_file: Some(_), .. })), .. }); "happy path diff")]
+        #[test_case::test_case(&mut ["diff", ".env", "export.json", "-p", &uuid::Uuid::new_v4().to_string(), "-n", "my-project"] => matches Err(ErrorKind::ArgumentConflict); "fails when conflicting diff projects")]
+        #[test_case::test_case(&mut ["diff", ".env"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when diff has no export")]
+        #[test_case::test_case(&mut ["merge", "a.json", "b.json", "teams/*/import.json", "--conflicts", "keep-last", "-o", "merged.json"] => matches Ok(Cli { command: Some(Command::Merge(MergeArgs { json_paths, conflicts: DuplicatePolicyArg::KeepLast, .. })), .. }) if json_paths.len() == 3; "happy path merge")]
+        #[test_case::test_case(&mut ["merge", "a.json", "b.json"] => matches Ok(Cli { command: Some(Command::Merge(MergeArgs { conflicts: DuplicatePolicyArg::Error, .. })), .. }); "defaults to failing on merge conflicts")]
+        #[test_case::test_case(&mut ["merge"] => matches Err(ErrorKind::MissingRequiredArgument); "fails when merge has no input")]
+        #[test_case::test_case(&mut [".env", "bws2env"] => matches Err(ErrorKind::ArgumentConflict); "fails when mixing conversion args with subcommand")]
+        #[test_case::test_case(&mut ["bws2env", "export.json", "--strict"] => matches Err(ErrorKind::UnknownArgument); "fails when mixing subcommand with conversion args")]
         #[test_case::test_case(&mut [] => matches Err(ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand); "help on missing args")]
         #[test_case::test_case(&mut ["--help"] => matches Err(ErrorKind::DisplayHelp); "help when requested")]
         #[test_case::test_case(&mut ["-h"] => matches Err(ErrorKind::DisplayHelp); "help when requested short")]
@@ -123,62 +711,441 @@ mod cli {
 
 fn main() -> anyhow::Result<()> {
     // Process CLI args
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
 
-    // Load dotenv struct from file
-    let dotenv = DotEnvFile::parse_from_file(cli.dotenv_path, cli.parse_comments, cli.verbose)?;
+    match cli.command.take() {
+        Some(Command::Bws2env(args)) => bws2env(args),
+        Some(Command::Validate(args)) => validate(args),
+        Some(Command::Diff(args)) => diff(args),
+        Some(Command::Merge(args)) => merge(args),
+        None => env2bws(cli),
+    }
+}
 
-    // Determine type of project assignment for secrets based on provided arguments
-    let project_assignment = match (
-        cli.project_assignment.project_id,
-        cli.project_assignment.new_project_name,
-    ) {
-        (None, Some(name)) => ProjectAssignment::New(name),
-        (Some(id), None) => ProjectAssignment::Existing(id),
-        (None, _) => ProjectAssignment::None,
-        _ => unreachable!(), // Should not be possible due to conflicts_with attribute on parser
+/// Converts one or more .env files into the Bitwarden Secrets Manager import JSON format
+fn env2bws(cli: Cli) -> anyhow::Result<()> {
+    let dotenv_paths = match cli.from_env {
+        true => vec![],
+        false => expand_input_paths(cli.dotenv_paths)?,
     };
+    if dotenv_paths
+        .iter()
+        .filter(|path| path.as_os_str() == STDIN_PATH)
+        .count()
+        > 1
+    {
+        return Err(anyhow!("Standard input can only be read once"));
+    }
 
-    // Prepare import payload in format expected by Bitwarden Secrets Manager
-    let payload = ImportPayload::from_dotenv(dotenv, project_assignment);
+    // Name the project of each file up front, so that mistakes are caught before reading any input
+    let file_assignments = if cli.project_assignment.project_per_file {
+        let mut mapping = FileProjectMapping::new(&cli.project_name_template)?;
+        if let Some(path) = &cli.project_map {
+            mapping = mapping.with_existing_projects_from_file(path)?;
+        }
 
-    // Depending on whether an output path is provided, either write out JSON result, or print to stdout
-    if let Some(path) = cli.output_file {
-        // Ensure path has .json extension and add it if not
-        let path = match path.extension() {
-            Some(ext) if ext == "json" => Ok(path),
-            Some(_) => Err(anyhow::anyhow!("Output file must have .json extension")),
-            _ => Ok(path.with_extension("json")),
-        }?;
+        let assignments = dotenv_paths
+            .iter()
+            .map(|path| mapping.assignment_for(path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Some(assignments)
+    } else {
+        None
+    };
 
-        // Write the JSON payload to the output file
-        eprintln!("Writing to file at {}", path.to_string_lossy());
-
-        let mut file = match OpenOptions::new()
-            .write(true)
-            .create_new(true) // Only create if it doesn't exist
-            .open(&path)
-        {
-            Ok(f) => f, // Success on first try
-            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
-                if cli.force_overwrite {
-                    eprintln!("File already exists in current location. Writing over contents because --force-overwrite was provided");
-                    // Retry with overwrite if allowed
-                    OpenOptions::new()
-                        .write(true)
-                        .truncate(true) // Overwrite existing file
-                        .open(&path)?
-                } else {
-                    return Err(anyhow!("File already exists at the current location. Try selecting a different output file or re-running with --force-overwrite"));
+    // Load dotenv structs from files
+    let options = ParseOptions {
+        parse_comments: cli.parse_comments,
+        strict: cli.strict,
+        verbose: cli.verbose,
+        section_pattern: cli
+            .project_assignment
+            .project_per_section
+            .then_some(cli.section_pattern),
+        annotations: cli.annotations,
+    };
+    let mut dotenvs = if cli.from_env {
+        // Capture the environment in place of any files
+        let patterns = cli
+            .env_prefix
+            .iter()
+            .map(|prefix| KeyPattern::prefix(prefix))
+            .chain(cli.env_var.iter().map(|key| KeyPattern::exact(key)))
+            .chain(cli.env_pattern)
+            .collect::<Vec<_>>();
+        let mut dotenv = DotEnvFile::from_environment(&patterns, cli.verbose);
+        report_warnings(&mut dotenv);
+        vec![dotenv]
+    } else {
+        dotenv_paths
+            .iter()
+            .map(|path| {
+                let mut dotenv = DotEnvFile::parse_from_file_with_options(path.clone(), &options)?;
+                report_warnings(&mut dotenv);
+                Ok(dotenv)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
+
+    // Expand and annotate each file on its own, so that references only resolve within the file
+    // and renamed keys take part in overriding variables of earlier files
+    for dotenv in &mut dotenvs {
+        // Resolve variable references if requested
+        if cli.expand {
+            dotenv.expand(cli.expand_from_env, cli.verbose)?;
+            report_warnings(dotenv);
+        }
+
+        // Skip, rename and annotate variables as requested by their annotations
+        dotenv.apply_annotations(cli.verbose);
+    }
+
+    // Either keep each file in its own project, or merge all files together and determine type
+    // of project assignment for secrets based on provided arguments
+    let mut dotenvs = if let Some(assignments) = file_assignments {
+        dotenvs.into_iter().zip(assignments).collect()
+    } else {
+        let args = cli.project_assignment;
+        let project_assignment = if args.project_per_section {
+            ProjectAssignment::NewPerSection
+        } else if !args.routes.is_empty() || args.routes_file.is_some() {
+            let mut rules = match args.routes_file {
+                Some(path) => RoutingRules::from_file(&path)?,
+                None => RoutingRules::default(),
+            };
+            rules.rules.extend(args.routes);
+            rules.fallback = cli.route_fallback.or(rules.fallback);
+            rules.match_all |= cli.match_all_routes;
+            ProjectAssignment::Rules(rules)
+        } else {
+            let mut targets = args
+                .project_id
+                .into_iter()
+                .map(ProjectTarget::ProjectId)
+                .chain(
+                    args.new_project_name
+                        .into_iter()
+                        .map(ProjectTarget::NewProject),
+                )
+                .collect::<Vec<_>>();
+
+            // Look up the projects given by name among the existing projects
+            if let Some(path) = cli.projects_file {
+                let listing = ProjectListing::from_file(&path)?;
+                for name in &args.project {
+                    targets.push(listing.resolve(name, cli.create_missing_projects)?);
                 }
             }
-            Err(e) => return Err(anyhow::anyhow!("Failed to create file: {}", e)),
+
+            match targets.as_slice() {
+                [] => ProjectAssignment::None,
+                [ProjectTarget::ProjectId(id)] => ProjectAssignment::Existing(*id),
+                [ProjectTarget::NewProject(name)] => ProjectAssignment::New(name.clone()),
+                // Several projects were given, to all of which every secret is assigned
+                _ => ProjectAssignment::Multiple(targets),
+            }
         };
+        vec![(DotEnvFile::merge(dotenvs, cli.verbose), project_assignment)]
+    };
+
+    for (dotenv, _) in &mut dotenvs {
+        // Check keys, values and notes before they are turned into secrets
+        dotenv.validate(cli.validation.into(), cli.verbose)?;
+        report_warnings(dotenv);
+
+        // Handle any keys that are defined more than once
+        dotenv.resolve_duplicates(cli.duplicates.into(), cli.verbose)?;
+        report_warnings(dotenv);
+
+        // Report where the final value of each secret came from
+        if cli.verbose {
+            dotenv
+                .iter()
+                .for_each(|env_var| eprintln!("{} from {}", env_var.key, env_var.location()));
+        }
+    }
+
+    // Prepare import payload in format expected by Bitwarden Secrets Manager
+    let mut payload = ImportPayload::from_dotenvs(dotenvs);
+
+    // Replace random IDs with ones derived from the input if requested
+    if cli.deterministic_ids || cli.canonical {
+        let namespace = cli
+            .id_seed
+            .as_deref()
+            .map_or(DEFAULT_ID_NAMESPACE, ImportPayload::id_namespace);
+        // Sort first, so that duplicate keys are told apart the same way whatever the input order
+        if cli.canonical {
+            payload.sort_secrets();
+        }
+        payload.derive_ids(&namespace);
+    }
+
+    // Format the payload as requested
+    let output_options = OutputOptions {
+        sort_secrets: cli.sort_secrets || cli.canonical,
+        sort_projects: cli.sort_projects || cli.canonical,
+        compact: cli.compact,
+        trailing_newline: !cli.no_trailing_newline,
+    };
+    let output = payload.to_json_with_options(&output_options)?;
+
+    // Depending on whether an output path is provided, either write out JSON result, or print to stdout
+    if let Some(path) = cli.output_file {
+        // Write the JSON payload to the output file
+        write_to_file(&json_output_path(path)?, &output, cli.force_overwrite)?;
+    } else {
+        print!("{output}");
+    }
 
-        file.write_all(serde_json::to_string_pretty(&payload)?.as_bytes())?;
+    Ok(())
+}
+
+/// Converts a Bitwarden Secrets Manager import or export JSON file back into a .env file
+fn bws2env(args: Bws2envArgs) -> anyhow::Result<()> {
+    let payload = ImportPayload::from_file(&args.json_path)?;
+
+    // Determine which project (if any) to limit the output to
+    let project_id = match (
+        args.project_filter.project_id,
+        args.project_filter.project_name,
+    ) {
+        (None, Some(name)) => Some(payload.find_project_by_name(&name)?.id),
+        (Some(id), None) => Some(id),
+        (None, _) => None,
+        _ => unreachable!(), // Should not be possible due to conflicts_with attribute on parser
+    };
+
+    let dotenv = match project_id {
+        Some(id) => DotEnvFile::from_secrets(payload.secrets_in_project(id)),
+        None => DotEnvFile::from_secrets(&payload.secrets),
+    };
+
+    // Depending on whether an output path is provided, either write out .env result, or print to stdout
+    if let Some(path) = args.output_file {
+        write_to_file(&path, &dotenv.to_string(), args.force_overwrite)?;
+    } else {
+        print!("{dotenv}");
+    }
+
+    Ok(())
+}
+
+/// Checks a Bitwarden Secrets Manager import JSON file for problems that would prevent it from being
+/// imported
+fn validate(args: ValidateArgs) -> anyhow::Result<()> {
+    let payload = ImportPayload::from_file(&args.json_path)?;
+
+    // Projects are only known to exist if they were listed
+    let existing_project_ids = match args.projects_file {
+        Some(path) => Some(
+            ProjectListing::from_file(&path)?
+                .projects
+                .into_iter()
+                .map(|project| project.id)
+                .chain(args.project_id)
+                .collect::<Vec<_>>(),
+        ),
+        None if !args.project_id.is_empty() => Some(args.project_id),
+        None => None,
+    };
+
+    let (errors, warnings): (Vec<_>, Vec<_>) = payload
+        .validate(existing_project_ids.as_deref())
+        .into_iter()
+        .partition(PayloadIssue::is_error);
+    warnings
+        .iter()
+        .for_each(|issue| eprintln!("Warning: {issue}"));
+
+    if !errors.is_empty() {
+        return Err(anyhow!(
+            "Found {} problem(s) in {path}:\n  {}",
+            errors.len(),
+            errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n  "),
+            path = args.json_path.to_string_lossy()
+        ));
+    }
+
+    eprintln!(
+        "{path} is valid, with {} project(s) and {} secret(s)",
+        payload.projects.len(),
+        payload.secrets.len(),
+        path = args.json_path.to_string_lossy()
+    );
+
+    Ok(())
+}
+
+/// Compares a .env file with the secrets of a Bitwarden Secrets Manager export JSON file
+fn diff(args: DiffArgs) -> anyhow::Result<()> {
+    let existing = ImportPayload::from_file(&args.export_path)?;
+    let mut dotenv = DotEnvFile::parse_from_file(args.dotenv_path, args.parse_comments, false)?;
+    report_warnings(&mut dotenv);
+
+    // Determine which project the variables are assigned to, and limit the existing secrets to
+    // that project
+    let target = match (args.project_id, args.project_name) {
+        (Some(id), None) => Some(ProjectTarget::ProjectId(id)),
+        (None, Some(name)) => Some(ProjectTarget::NewProject(name)),
+        (None, None) => None,
+        _ => unreachable!(), // Should not be possible due to conflicts_with attribute on parser
+    };
+    let existing = existing.secrets_for_target(target.as_ref())?;
+    let project_assignment = match target {
+        Some(ProjectTarget::ProjectId(id)) => ProjectAssignment::Existing(id),
+        Some(ProjectTarget::NewProject(name)) => ProjectAssignment::New(name),
+        None => ProjectAssignment::None,
+    };
+    let wanted = ImportPayload::from_dotenv(dotenv, project_assignment);
+
+    let diff = PayloadDiff::new(&wanted, &existing, args.parse_comments);
+    for project in &diff.projects {
+        println!("{project}:");
+        for change in &project.changes {
+            println!("  {}", change.describe(args.show_values));
+        }
+    }
+
+    let count =
+        |predicate: fn(&SecretChange) -> bool| diff.changes().filter(|c| predicate(c)).count();
+    println!(
+        "{} added, {} removed, {} changed",
+        count(|change| matches!(change, SecretChange::Added(_))),
+        count(|change| matches!(change, SecretChange::Removed(_))),
+        count(|change| matches!(change, SecretChange::Changed { .. })),
+    );
+
+    // Write out the secrets that need to be imported if requested
+    if let Some(path) = args.output_file {
+        let output = diff
+            .incremental_payload()
+            .to_json_with_options(&OutputOptions::default())?;
+        write_to_file(&json_output_path(path)?, &output, args.force_overwrite)?;
+    }
+
+    Ok(())
+}
+
+/// Combines several Bitwarden Secrets Manager import JSON files into one
+fn merge(args: MergeArgs) -> anyhow::Result<()> {
+    let payloads = expand_input_paths(args.json_paths)?
+        .iter()
+        .map(|path| ImportPayload::from_file(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (payload, warnings) = ImportPayload::merge(payloads, args.conflicts.into(), args.verbose)?;
+    warnings
+        .iter()
+        .for_each(|warning| eprintln!("Warning: {warning}"));
+
+    let output = payload.to_json_with_options(&OutputOptions::default())?;
+    if let Some(path) = args.output_file {
+        write_to_file(&json_output_path(path)?, &output, args.force_overwrite)?;
     } else {
-        println!("{}", serde_json::to_string_pretty(&payload)?);
+        print!("{output}");
     }
 
     Ok(())
 }
+
+/// Ensures the given output path has a .json extension, adding it if there is no extension.
+fn json_output_path(path: PathBuf) -> anyhow::Result<PathBuf> {
+    match path.extension() {
+        Some(ext) if ext == "json" => Ok(path),
+        Some(_) => Err(anyhow::anyhow!("Output file must have .json extension")),
+        _ => Ok(path.with_extension("json")),
+    }
+}
+
+/// Expands any glob patterns among the given input paths, keeping all other paths as given. Paths
+/// of existing files are never treated as patterns, even if they contain glob metacharacters (e.g.
+/// `config[prod].env`).
+///
+/// # Errors
+///
+/// Will return error if a pattern is invalid or doesn't match any file.
+fn expand_input_paths(paths: Vec<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    let mut expanded = vec![];
+
+    for path in paths {
+        let pattern = path.to_string_lossy();
+        if glob::Pattern::escape(&pattern) == pattern || path.exists() {
+            expanded.push(path);
+            continue;
+        }
+
+        let matches = glob::glob(&pattern)
+            .map_err(|e| anyhow!("Invalid pattern {pattern}: {e}"))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(anyhow!("No files match the pattern {pattern}"));
+        }
+        expanded.extend(matches);
+    }
+
+    Ok(expanded)
+}
+
+/// Writes the warnings recorded while parsing or processing the given variables to stderr.
+fn report_warnings(dotenv: &mut DotEnvFile) {
+    for warning in dotenv.take_warnings() {
+        eprintln!("Warning: {warning}");
+    }
+}
+
+/// Writes the given contents to a new file at the given path.
+///
+/// If the file already exists, it is only overwritten when `force_overwrite` is set.
+fn write_to_file(path: &Path, contents: &str, force_overwrite: bool) -> anyhow::Result<()> {
+    eprintln!("Writing to file at {}", path.to_string_lossy());
+
+    let mut file = match OpenOptions::new()
+        .write(true)
+        .create_new(true) // Only create if it doesn't exist
+        .open(path)
+    {
+        Ok(f) => f, // Success on first try
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            if force_overwrite {
+                eprintln!("File already exists in current location. Writing over contents because --force-overwrite was provided");
+                // Retry with overwrite if allowed
+                OpenOptions::new()
+                    .write(true)
+                    .truncate(true) // Overwrite existing file
+                    .open(path)?
+            } else {
+                return Err(anyhow!("File already exists at the current location. Try selecting a different output file or re-running with --force-overwrite"));
+            }
+        }
+        Err(e) => return Err(anyhow::anyhow!("Failed to create file: {}", e)),
+    };
+
+    file.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod input_path_tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn keeps_existing_paths_with_glob_characters() {
+        let dir = tempfile::tempdir().expect("could not create temp dir");
+        let path = dir.path().join("config[prod].env");
+        fs::write(&path, "KEY=value").expect("could not write file");
+
+        let expanded = expand_input_paths(vec![path.clone()]).expect("failed to expand paths");
+        assert_eq!(expanded, vec![path]);
+
+        let pattern = dir.path().join("*.env");
+        let expanded = expand_input_paths(vec![pattern]).expect("failed to expand pattern");
+        assert_eq!(expanded.len(), 1);
+    }
+}
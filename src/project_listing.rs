@@ -0,0 +1,170 @@
+//! Lookup of existing projects by name, from a listing of the projects in Bitwarden Secrets Manager
+use crate::{error::load_error, Project, ProjectTarget};
+use anyhow::anyhow;
+use std::{fs, path::Path};
+
+/// The existing projects of a Bitwarden Secrets Manager organization
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProjectListing {
+    pub projects: Vec<Project>,
+}
+
+/// The formats a listing of projects may be read from
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum ListingFormat {
+    /// Output of `bws project list`
+    List(Vec<Project>),
+    /// Export or import JSON, of which only the projects are used
+    Export { projects: Vec<Project> },
+}
+
+impl ProjectListing {
+    /// Parses a listing of projects from either the JSON output of `bws project list`, or a
+    /// Bitwarden Secrets Manager export (or import) JSON document. Fields other than the ID and name
+    /// of each project are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if the input is in neither format.
+    pub fn from_json(input: &str) -> anyhow::Result<Self> {
+        let listing = serde_json::from_str::<ListingFormat>(input).map_err(|e| {
+            anyhow!(
+                "Expected the output of `bws project list`, or a Bitwarden Secrets Manager \
+                 export: {e}"
+            )
+        })?;
+        let projects = match listing {
+            ListingFormat::List(projects) | ListingFormat::Export { projects } => projects,
+        };
+
+        Ok(Self { projects })
+    }
+
+    /// Reads a listing of projects from a file, in either of the formats accepted by
+    /// [`ProjectListing::from_json`].
+    ///
+    /// # Errors
+    ///
+    /// Will return error if file cannot be read, or if its contents are in neither format.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let raw = fs::read_to_string(path).map_err(|e| load_error(path, e))?;
+
+        Self::from_json(&raw).map_err(|e| {
+            anyhow!(
+                "Failed to read projects from {path}: {e}",
+                path = path.to_string_lossy()
+            )
+        })
+    }
+
+    /// Finds the single project with the given name.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if no project, or more than one project, has the given name.
+    pub fn find_by_name(&self, name: &str) -> anyhow::Result<&Project> {
+        find_by_name(&self.projects, name)
+    }
+
+    /// Resolves the given name into the existing project having that name. If no such project
+    /// exists and `create_missing` is set, a new project with that name is used instead.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if more than one project has the given name, or if no project does and
+    /// `create_missing` is not set.
+    pub fn resolve(&self, name: &str, create_missing: bool) -> anyhow::Result<ProjectTarget> {
+        if create_missing && !self.projects.iter().any(|p| p.name == name) {
+            return Ok(ProjectTarget::NewProject(name.to_owned()));
+        }
+
+        self.find_by_name(name)
+            .map(|project| ProjectTarget::ProjectId(project.id))
+    }
+}
+
+/// Finds the single project with the given name among the given projects.
+pub(crate) fn find_by_name<'a>(projects: &'a [Project], name: &str) -> anyhow::Result<&'a Project> {
+    let mut matches = projects.iter().filter(|p| p.name == name);
+    match (matches.next(), matches.next()) {
+        (Some(project), None) => Ok(project),
+        (None, _) => Err(anyhow!("No project named {name:?} was found")),
+        (Some(_), Some(_)) => Err(anyhow!(
+            "More than one project is named {name:?}. Try selecting the project by ID instead"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod project_listing_tests {
+    use super::*;
+
+    const PROJECT_LIST: &str = r#"[
+        {
+            "object": "project",
+            "id": "00000000-0000-0000-0000-000000000001",
+            "organizationId": "00000000-0000-0000-0000-0000000000ff",
+            "name": "Payments",
+            "creationDate": "2025-01-01T00:00:00.000000Z",
+            "revisionDate": "2025-01-01T00:00:00.000000Z"
+        },
+        {
+            "object": "project",
+            "id": "00000000-0000-0000-0000-000000000002",
+            "organizationId": "00000000-0000-0000-0000-0000000000ff",
+            "name": "Duplicated",
+            "creationDate": "2025-01-01T00:00:00.000000Z",
+            "revisionDate": "2025-01-01T00:00:00.000000Z"
+        },
+        {
+            "object": "project",
+            "id": "00000000-0000-0000-0000-000000000003",
+            "organizationId": "00000000-0000-0000-0000-0000000000ff",
+            "name": "Duplicated",
+            "creationDate": "2025-01-01T00:00:00.000000Z",
+            "revisionDate": "2025-01-01T00:00:00.000000Z"
+        }
+    ]"#;
+
+    #[test]
+    fn reads_bws_project_list() {
+        let listing = ProjectListing::from_json(PROJECT_LIST).expect("invalid listing");
+        assert_eq!(listing.projects.len(), 3);
+        assert_eq!(listing.projects[0].name, "Payments");
+    }
+
+    #[test]
+    fn reads_projects_of_export() {
+        let listing =
+            ProjectListing::from_json(include_str!("../sample.json")).expect("invalid listing");
+        assert_eq!(listing.projects, vec![]);
+
+        let listing = ProjectListing::from_json(
+            r#"{"projects": [{"id": "00000000-0000-0000-0000-000000000001", "name": "A"}], "secrets": []}"#,
+        )
+        .expect("invalid listing");
+        assert_eq!(listing.projects[0].name, "A");
+    }
+
+    #[test]
+    fn rejects_other_json() {
+        assert!(ProjectListing::from_json(r#"{"name": "A"}"#).is_err());
+
+        let err = ProjectListing::from_json("[").expect_err("truncated listing should fail");
+        assert!(err
+            .to_string()
+            .ends_with("EOF while parsing a list at line 1 column 1"));
+    }
+
+    #[test_case::test_case("Payments", false => matches Ok(ProjectTarget::ProjectId(id)) if id == uuid::Uuid::from_u128(1); "resolves existing project")]
+    #[test_case::test_case("Payments", true => matches Ok(ProjectTarget::ProjectId(id)) if id == uuid::Uuid::from_u128(1); "prefers existing project when creating")]
+    #[test_case::test_case("Missing", false => matches Err(e) if e.to_string().contains("No project named"); "fails on missing project")]
+    #[test_case::test_case("Missing", true => matches Ok(ProjectTarget::NewProject(name)) if name == "Missing"; "creates missing project when requested")]
+    #[test_case::test_case("Duplicated", true => matches Err(e) if e.to_string().contains("More than one project"); "fails on ambiguous project")]
+    fn resolves_projects(name: &str, create_missing: bool) -> anyhow::Result<ProjectTarget> {
+        ProjectListing::from_json(PROJECT_LIST)
+            .expect("invalid listing")
+            .resolve(name, create_missing)
+    }
+}
@@ -0,0 +1,210 @@
+//! Assignment of secrets to projects based on the `.env` file they were parsed from
+use crate::{dotenv::STDIN_PATH, error::load_error, ProjectAssignment};
+use anyhow::anyhow;
+use std::{collections::HashMap, fs, path::Path};
+
+/// Template used to name projects when none is given, naming each project after the path of its file
+pub const DEFAULT_PROJECT_NAME_TEMPLATE: &str = "{path}";
+
+/// Determines the project that the secrets of each `.env` file are assigned to.
+///
+/// Each file is given a project name by filling in a template, which may contain the following
+/// placeholders:
+///
+/// - `{path}`: the path of the file, as given (e.g. `services/api/.env.production`)
+/// - `{dir}`: the name of the directory containing the file (e.g. `api`)
+/// - `{file}`: the name of the file (e.g. `.env.production`)
+/// - `{env}`: the environment named by the file, which is whatever follows `.env.` or precedes
+///   `.env` in its name (e.g. `production`), or empty for a plain `.env` file
+///
+/// If the resulting name appears in the table of existing projects, the secrets are assigned to
+/// that existing project. Otherwise, a new project with that name is created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileProjectMapping {
+    template: String,
+    existing: HashMap<String, uuid::Uuid>,
+}
+
+impl Default for FileProjectMapping {
+    fn default() -> Self {
+        Self {
+            template: DEFAULT_PROJECT_NAME_TEMPLATE.to_owned(),
+            existing: HashMap::new(),
+        }
+    }
+}
+
+impl FileProjectMapping {
+    /// Constructs a mapping that names projects using the given template.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if the template contains an unknown or unclosed placeholder.
+    pub fn new(template: &str) -> anyhow::Result<Self> {
+        // Fill in the template once to catch mistakes before any file is processed
+        render(template, Path::new(".env"))?;
+
+        Ok(Self {
+            template: template.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    /// Maps project names to the IDs of existing projects that should be used in their place.
+    pub fn with_existing_projects(mut self, existing: HashMap<String, uuid::Uuid>) -> Self {
+        self.existing = existing;
+        self
+    }
+
+    /// Reads the table of existing projects from a JSON file containing an object that maps
+    /// project names to project IDs, e.g. `{"api": "<project-id>"}`.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if file cannot be read, or if its contents are not such an object.
+    pub fn with_existing_projects_from_file(self, path: &Path) -> anyhow::Result<Self> {
+        let raw = fs::read_to_string(path).map_err(|e| load_error(path, e))?;
+        let existing = serde_json::from_str(&raw).map_err(|e| {
+            anyhow!(
+                "Failed to read {path} as a mapping of project names to IDs: {e}",
+                path = path.to_string_lossy()
+            )
+        })?;
+
+        Ok(self.with_existing_projects(existing))
+    }
+
+    /// Determines the name of the project for the file at the given path.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if the template contains an unknown or unclosed placeholder, or if the
+    /// path is [`STDIN_PATH`], which has no file to name a project after.
+    pub fn project_name(&self, path: &Path) -> anyhow::Result<String> {
+        if path.as_os_str() == STDIN_PATH {
+            return Err(anyhow!(
+                "Standard input has no file name to name a project after"
+            ));
+        }
+        render(&self.template, path)
+    }
+
+    /// Determines how the secrets of the file at the given path are assigned to a project.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if the template contains an unknown or unclosed placeholder, or if the
+    /// path is [`STDIN_PATH`].
+    pub fn assignment_for(&self, path: &Path) -> anyhow::Result<ProjectAssignment> {
+        let name = self.project_name(path)?;
+        Ok(match self.existing.get(&name) {
+            Some(id) => ProjectAssignment::Existing(*id),
+            None => ProjectAssignment::New(name),
+        })
+    }
+}
+
+/// Fills in the placeholders of the given template using the given path.
+fn render(template: &str, path: &Path) -> anyhow::Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed placeholder in project name template {template:?}"))?
+            + start;
+
+        let value = match &rest[start + 1..end] {
+            "path" => path.to_string_lossy().into_owned(),
+            "dir" => dir_name(path),
+            "file" => file_name(path),
+            "env" => env_name(&file_name(path)).to_owned(),
+            placeholder => {
+                return Err(anyhow!(
+                    "Unknown placeholder {{{placeholder}}} in project name template {template:?}"
+                ))
+            }
+        };
+        output.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Finds the name of the directory containing the given path, resolving it against the current
+/// directory if the path doesn't name one itself.
+fn dir_name(path: &Path) -> String {
+    match path.parent() {
+        Some(parent) if parent.file_name().is_some() => file_name(parent),
+        _ => fs::canonicalize(path)
+            .ok()
+            .and_then(|path| path.parent().map(file_name))
+            .unwrap_or_default(),
+    }
+}
+
+/// Extracts the environment named by a file name like `.env.production` or `production.env`.
+fn env_name(file_name: &str) -> &str {
+    file_name
+        .strip_prefix(".env.")
+        .or_else(|| file_name.strip_suffix(".env"))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod project_mapping_tests {
+    use super::*;
+
+    #[test_case::test_case("{path}", "services/api/.env" => "services/api/.env"; "fills in path")]
+    #[test_case::test_case("{dir}", "services/api/.env" => "api"; "fills in directory")]
+    #[test_case::test_case("{file}", "services/api/.env.local" => ".env.local"; "fills in file name")]
+    #[test_case::test_case("{dir}-{env}", "services/api/.env.production" => "api-production"; "fills in environment after extension")]
+    #[test_case::test_case("{dir}-{env}", "services/api/staging.env" => "api-staging"; "fills in environment before extension")]
+    #[test_case::test_case("{dir} ({env})", "services/api/.env" => "api ()"; "leaves environment empty for plain file")]
+    #[test_case::test_case("Project {dir}!", "api/.env" => "Project api!"; "keeps text outside of placeholders")]
+    fn renders_project_names(template: &str, path: &str) -> String {
+        FileProjectMapping::new(template)
+            .expect("invalid template")
+            .project_name(Path::new(path))
+            .expect("failed to render template")
+    }
+
+    #[test_case::test_case("{directory}"; "rejects unknown placeholder")]
+    #[test_case::test_case("{dir"; "rejects unclosed placeholder")]
+    fn rejects_invalid_templates(template: &str) {
+        assert!(FileProjectMapping::new(template).is_err());
+    }
+
+    #[test]
+    fn rejects_stdin() {
+        let mapping = FileProjectMapping::default();
+        assert!(mapping.assignment_for(Path::new(STDIN_PATH)).is_err());
+    }
+
+    #[test]
+    fn assigns_existing_projects_by_name() {
+        let existing_id = uuid::Uuid::new_v4();
+        let mapping = FileProjectMapping::new("{dir}")
+            .expect("invalid template")
+            .with_existing_projects(HashMap::from([("api".to_owned(), existing_id)]));
+
+        assert!(matches!(
+            mapping.assignment_for(Path::new("services/api/.env")),
+            Ok(ProjectAssignment::Existing(id)) if id == existing_id
+        ));
+        assert!(matches!(
+            mapping.assignment_for(Path::new("services/web/.env")),
+            Ok(ProjectAssignment::New(name)) if name == "web"
+        ));
+    }
+}
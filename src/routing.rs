@@ -0,0 +1,335 @@
+//! Assignment of secrets to projects based on rules matching their keys
+use crate::error::load_error;
+use anyhow::anyhow;
+use regex::Regex;
+use std::{fmt, fs, path::Path, str::FromStr};
+
+/// A pattern matched against the keys of secrets
+#[derive(Debug, Clone)]
+pub enum KeyPattern {
+    /// A glob pattern that must match the whole key, e.g. `SERVICE_1_*`
+    Glob(glob::Pattern),
+    /// A regular expression that must match somewhere within the key, e.g. `^SERVICE_\d+_`
+    Regex(Regex),
+}
+
+impl KeyPattern {
+    /// Determines whether the given key matches the pattern.
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            Self::Glob(pattern) => pattern.matches(key),
+            Self::Regex(pattern) => pattern.is_match(key),
+        }
+    }
+
+    /// Constructs a glob pattern.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if the pattern is not a valid glob pattern.
+    pub fn glob(pattern: &str) -> anyhow::Result<Self> {
+        glob::Pattern::new(pattern)
+            .map(Self::Glob)
+            .map_err(|e| anyhow!("Invalid glob pattern {pattern:?}: {e}"))
+    }
+
+    /// Constructs a pattern matching keys that start with the given prefix.
+    pub fn prefix(prefix: &str) -> Self {
+        Self::Glob(
+            glob::Pattern::new(&format!("{}*", glob::Pattern::escape(prefix)))
+                .expect("escaped pattern is valid"),
+        )
+    }
+
+    /// Constructs a pattern matching only the given key.
+    pub fn exact(key: &str) -> Self {
+        Self::Glob(
+            glob::Pattern::new(&glob::Pattern::escape(key)).expect("escaped pattern is valid"),
+        )
+    }
+
+    /// Constructs a regular expression pattern.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if the pattern is not a valid regular expression.
+    pub fn regex(pattern: &str) -> anyhow::Result<Self> {
+        Regex::new(pattern)
+            .map(Self::Regex)
+            .map_err(|e| anyhow!("Invalid regular expression {pattern:?}: {e}"))
+    }
+}
+
+/// Patterns are considered equal if they are of the same kind and written the same way
+impl PartialEq for KeyPattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Glob(a), Self::Glob(b)) => a == b,
+            (Self::Regex(a), Self::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for KeyPattern {}
+
+impl fmt::Display for KeyPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Glob(pattern) => write!(f, "{pattern}"),
+            Self::Regex(pattern) => write!(f, "/{pattern}/"),
+        }
+    }
+}
+
+/// The project that secrets matching a rule are assigned to
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProjectTarget {
+    /// An existing project having the given ID
+    ProjectId(uuid::Uuid),
+    /// A new project having the given name
+    NewProject(String),
+}
+
+/// Parses a project ID, or otherwise the name of a new project
+impl FromStr for ProjectTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Err(anyhow!("Project must be an ID or a name"));
+        }
+
+        Ok(match uuid::Uuid::parse_str(s) {
+            Ok(id) => Self::ProjectId(id),
+            Err(_) => Self::NewProject(s.to_owned()),
+        })
+    }
+}
+
+/// A rule assigning secrets whose key matches a pattern to a project
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingRule {
+    pub pattern: KeyPattern,
+    pub target: ProjectTarget,
+}
+
+/// Parses a rule of the form `GLOB=PROJECT`, where `PROJECT` is the ID of an existing project or
+/// the name of a new project
+impl FromStr for RoutingRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, target) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected a rule of the form PATTERN=PROJECT"))?;
+
+        Ok(Self {
+            pattern: KeyPattern::glob(pattern)?,
+            target: target.parse()?,
+        })
+    }
+}
+
+/// An ordered list of rules assigning secrets to projects based on their keys.
+///
+/// Each secret is assigned to the project of the first rule matching its key, or to the projects of
+/// every rule matching its key if `match_all` is set. Secrets matching no rule are assigned to the
+/// fallback project, if any, and are otherwise left unassigned.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutingRules {
+    pub rules: Vec<RoutingRule>,
+    pub fallback: Option<ProjectTarget>,
+    pub match_all: bool,
+}
+
+/// Rules as written in a configuration file, before their patterns are compiled
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct RoutingConfig {
+    rules: Vec<RuleConfig>,
+    fallback: Option<ProjectTarget>,
+    #[serde(default)]
+    match_all: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct RuleConfig {
+    #[serde(flatten)]
+    pattern: PatternConfig,
+    #[serde(flatten)]
+    target: ProjectTarget,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum PatternConfig {
+    Glob(String),
+    Regex(String),
+}
+
+impl RoutingRules {
+    /// Finds the projects that the secret with the given key is assigned to, which may be empty.
+    pub fn targets_for(&self, key: &str) -> Vec<&ProjectTarget> {
+        let mut matching = self.rules.iter().filter(|rule| rule.pattern.matches(key));
+
+        let targets: Vec<_> = if self.match_all {
+            matching.map(|rule| &rule.target).collect()
+        } else {
+            matching
+                .next()
+                .map(|rule| &rule.target)
+                .into_iter()
+                .collect()
+        };
+
+        match self.fallback.as_ref() {
+            Some(fallback) if targets.is_empty() => vec![fallback],
+            _ => targets,
+        }
+    }
+
+    /// Parses rules from a JSON document of the form:
+    ///
+    /// ```json
+    /// {
+    ///     "rules": [
+    ///         { "glob": "SERVICE_1_*", "newProject": "Service 1" },
+    ///         { "regex": "^SERVICE_2_", "projectId": "00000000-0000-0000-0000-000000000000" }
+    ///     ],
+    ///     "fallback": { "newProject": "Shared" },
+    ///     "matchAll": false
+    /// }
+    /// ```
+    ///
+    /// The fallback and `matchAll` fields are optional.
+    ///
+    /// # Errors
+    ///
+    /// Will return error if the document is not in the expected format, or if a pattern is invalid.
+    pub fn from_json(input: &str) -> anyhow::Result<Self> {
+        let config = serde_json::from_str::<RoutingConfig>(input)?;
+
+        let rules = config
+            .rules
+            .into_iter()
+            .map(|rule| {
+                let pattern = match rule.pattern {
+                    PatternConfig::Glob(pattern) => KeyPattern::glob(&pattern)?,
+                    PatternConfig::Regex(pattern) => KeyPattern::regex(&pattern)?,
+                };
+                Ok(RoutingRule {
+                    pattern,
+                    target: rule.target,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            rules,
+            fallback: config.fallback,
+            match_all: config.match_all,
+        })
+    }
+
+    /// Reads rules from a JSON file, in the format described by [`RoutingRules::from_json`].
+    ///
+    /// # Errors
+    ///
+    /// Will return error if file cannot be read, or if its contents are invalid.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let raw = fs::read_to_string(path).map_err(|e| load_error(path, e))?;
+
+        Self::from_json(&raw).map_err(|e| {
+            anyhow!(
+                "Failed to read routing rules from {path}: {e}",
+                path = path.to_string_lossy()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod routing_tests {
+    use super::*;
+
+    const EXISTING_ID: &str = "8f0ac4b6-3e2c-4d5f-9a4e-0c1d2b3a4f5e";
+
+    fn rules() -> RoutingRules {
+        RoutingRules::from_json(&format!(
+            r#"{{
+                "rules": [
+                    {{ "glob": "SERVICE_1_*", "newProject": "Service 1" }},
+                    {{ "regex": "^SERVICE_\\d+_", "projectId": "{EXISTING_ID}" }}
+                ]
+            }}"#
+        ))
+        .expect("invalid rules")
+    }
+
+    #[test_case::test_case("SERVICE_1_PORT" => Some(ProjectTarget::NewProject("Service 1".to_owned())); "uses first matching rule")]
+    #[test_case::test_case("SERVICE_2_PORT" => Some(ProjectTarget::ProjectId(EXISTING_ID.parse().unwrap())); "falls through to later rules")]
+    #[test_case::test_case("OTHER_SERVICE_1_PORT" => None; "requires glob to match whole key")]
+    #[test_case::test_case("SHARED" => None; "leaves unmatched keys unassigned")]
+    fn routes_keys(key: &str) -> Option<ProjectTarget> {
+        let rules = rules();
+        let targets = rules.targets_for(key);
+        assert!(targets.len() <= 1);
+        targets.first().copied().cloned()
+    }
+
+    #[test]
+    fn routes_keys_to_every_matching_rule() {
+        let rules = RoutingRules {
+            match_all: true,
+            ..rules()
+        };
+        assert_eq!(
+            rules.targets_for("SERVICE_1_PORT"),
+            vec![
+                &ProjectTarget::NewProject("Service 1".to_owned()),
+                &ProjectTarget::ProjectId(EXISTING_ID.parse().unwrap())
+            ]
+        );
+        assert!(rules.targets_for("SHARED").is_empty());
+    }
+
+    #[test]
+    fn routes_unmatched_keys_to_fallback() {
+        let rules = RoutingRules {
+            fallback: Some(ProjectTarget::NewProject("Shared".to_owned())),
+            ..rules()
+        };
+        assert_eq!(
+            rules.targets_for("SHARED"),
+            vec![&ProjectTarget::NewProject("Shared".to_owned())]
+        );
+        assert_eq!(rules.targets_for("SERVICE_1_PORT").len(), 1);
+    }
+
+    #[test_case::test_case(KeyPattern::prefix("CI_"), "CI_JOB_ID" => true; "matches prefix")]
+    #[test_case::test_case(KeyPattern::prefix("CI_"), "GITLAB_CI" => false; "requires prefix at start")]
+    #[test_case::test_case(KeyPattern::prefix("A[1]"), "A[1]_KEY" => true; "escapes prefix")]
+    #[test_case::test_case(KeyPattern::exact("HOME"), "HOME" => true; "matches exact key")]
+    #[test_case::test_case(KeyPattern::exact("HOME"), "HOMEPATH" => false; "rejects longer key")]
+    fn matches_keys(pattern: KeyPattern, key: &str) -> bool {
+        pattern.matches(key)
+    }
+
+    #[test_case::test_case("SERVICE_1_*=Service 1" => matches Ok(RoutingRule { target: ProjectTarget::NewProject(_), .. }); "parses new project rule")]
+    #[test_case::test_case(&format!("SERVICE_1_*={EXISTING_ID}") => matches Ok(RoutingRule { target: ProjectTarget::ProjectId(_), .. }); "parses existing project rule")]
+    #[test_case::test_case("SERVICE_1_*" => matches Err(_); "rejects rule without project")]
+    #[test_case::test_case("SERVICE_1_*=" => matches Err(_); "rejects rule with empty project")]
+    #[test_case::test_case("[=Service 1" => matches Err(_); "rejects invalid glob")]
+    fn parses_rules(input: &str) -> anyhow::Result<RoutingRule> {
+        input.parse()
+    }
+
+    #[test_case::test_case(r#"{ "rules": [{ "regex": "(", "newProject": "A" }] }"#; "rejects invalid regex")]
+    #[test_case::test_case(r#"{ "rules": [{ "newProject": "A" }] }"#; "rejects rule without pattern")]
+    #[test_case::test_case(r#"{ "rules": [], "fallbak": { "newProject": "A" } }"#; "rejects unknown fields")]
+    fn rejects_invalid_config(input: &str) {
+        assert!(RoutingRules::from_json(input).is_err());
+    }
+}
@@ -0,0 +1,362 @@
+//! Validation of secrets against POSIX naming rules and Bitwarden Secrets Manager constraints
+use std::fmt;
+use uuid::Uuid;
+
+/// Maximum number of characters allowed in a secret's key by Bitwarden Secrets Manager
+pub const MAX_KEY_LENGTH: usize = 500;
+
+/// Maximum number of characters allowed in a secret's value by Bitwarden Secrets Manager
+pub const MAX_VALUE_LENGTH: usize = 25_000;
+
+/// Maximum number of characters allowed in a secret's note by Bitwarden Secrets Manager
+pub const MAX_NOTE_LENGTH: usize = 7_000;
+
+/// The way in which problems found during validation are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationPolicy {
+    /// Don't check secrets at all
+    #[default]
+    Off,
+    /// Fail if any problem is found
+    Error,
+    /// Report each problem as a warning, but otherwise leave the secret as-is
+    Warn,
+    /// Fix problems where possible (renaming keys, truncating notes, or dropping secrets without a
+    /// key), and fail on any problem that cannot be fixed
+    Fix,
+}
+
+/// A single problem found with a secret
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// The key is empty
+    EmptyKey,
+    /// The key is not a valid POSIX environment variable name
+    InvalidKey,
+    /// The key is longer than [`MAX_KEY_LENGTH`], holding the actual length
+    KeyTooLong(usize),
+    /// The value is longer than [`MAX_VALUE_LENGTH`], holding the actual length
+    ValueTooLong(usize),
+    /// The note is longer than [`MAX_NOTE_LENGTH`], holding the actual length
+    NoteTooLong(usize),
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyKey => write!(f, "key is empty"),
+            Self::InvalidKey => write!(
+                f,
+                "key is not a valid environment variable name (expected letters, digits and underscores, not starting with a digit)"
+            ),
+            Self::KeyTooLong(length) => write!(
+                f,
+                "key is {length} characters long, exceeding the limit of {MAX_KEY_LENGTH}"
+            ),
+            Self::ValueTooLong(length) => write!(
+                f,
+                "value is {length} characters long, exceeding the limit of {MAX_VALUE_LENGTH}"
+            ),
+            Self::NoteTooLong(length) => write!(
+                f,
+                "note is {length} characters long, exceeding the limit of {MAX_NOTE_LENGTH}"
+            ),
+        }
+    }
+}
+
+/// A single problem found with a Bitwarden Secrets Manager import JSON document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayloadIssue {
+    /// More than one project has the given ID
+    DuplicateProjectId(Uuid),
+    /// More than one secret has the given ID
+    DuplicateSecretId(Uuid),
+    /// More than one secret assigned to the same project (or more than one unassigned secret) has
+    /// the given key
+    DuplicateKey {
+        key: String,
+        project_id: Option<Uuid>,
+    },
+    /// A secret lists the same project more than once
+    DuplicateProjectAssignment { key: String, project_id: Uuid },
+    /// Secrets are assigned to a project that isn't defined in the document, and is assumed to be
+    /// an existing project
+    ExternalProject(Uuid),
+    /// A secret is assigned to a project that is neither defined in the document nor known to exist
+    UnknownProject { key: String, project_id: Uuid },
+    /// The fields of a secret break a rule checked by [`validate_secret`]
+    Secret { key: String, issue: ValidationIssue },
+}
+
+impl PayloadIssue {
+    /// Determines whether the issue would prevent the document from being imported. Other issues
+    /// are only worth pointing out.
+    pub fn is_error(&self) -> bool {
+        !matches!(
+            self,
+            Self::ExternalProject(_)
+                | Self::Secret {
+                    issue: ValidationIssue::InvalidKey,
+                    ..
+                }
+        )
+    }
+}
+
+impl fmt::Display for PayloadIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateProjectId(id) => write!(f, "more than one project has ID {id}"),
+            Self::DuplicateSecretId(id) => write!(f, "more than one secret has ID {id}"),
+            Self::DuplicateKey {
+                key,
+                project_id: Some(project_id),
+            } => write!(
+                f,
+                "{key:?}: more than one secret with this key is assigned to project {project_id}"
+            ),
+            Self::DuplicateKey {
+                key,
+                project_id: None,
+            } => write!(
+                f,
+                "{key:?}: more than one unassigned secret has this key"
+            ),
+            Self::DuplicateProjectAssignment { key, project_id } => write!(
+                f,
+                "{key:?}: assigned to project {project_id} more than once"
+            ),
+            Self::ExternalProject(id) => write!(
+                f,
+                "project {id} isn't defined in the file, so it must already exist"
+            ),
+            Self::UnknownProject { key, project_id } => write!(
+                f,
+                "{key:?}: assigned to project {project_id}, which isn't defined in the file or known to exist"
+            ),
+            Self::Secret { key, issue } => write!(f, "{key:?}: {issue}"),
+        }
+    }
+}
+
+/// Checks the fields of a single secret, returning all problems found.
+pub fn validate_secret(key: &str, value: &str, note: &str) -> Vec<ValidationIssue> {
+    let mut issues = vec![];
+
+    if key.is_empty() {
+        issues.push(ValidationIssue::EmptyKey);
+    } else if !is_posix_name(key) {
+        issues.push(ValidationIssue::InvalidKey);
+    }
+
+    let key_length = key.chars().count();
+    if key_length > MAX_KEY_LENGTH {
+        issues.push(ValidationIssue::KeyTooLong(key_length));
+    }
+
+    let value_length = value.chars().count();
+    if value_length > MAX_VALUE_LENGTH {
+        issues.push(ValidationIssue::ValueTooLong(value_length));
+    }
+
+    let note_length = note.chars().count();
+    if note_length > MAX_NOTE_LENGTH {
+        issues.push(ValidationIssue::NoteTooLong(note_length));
+    }
+
+    issues
+}
+
+/// Checks the fields of a single secret, handling any problems found according to the given
+/// policy.
+///
+/// Problems that are fixed or only warned about are added to `warnings`, with a single warning for
+/// all problems with the key, written once it has been fixed. Problems that cause a failure are
+/// added to `failures`. Returns whether the secret should be kept.
+pub(crate) fn enforce_policy(
+    policy: ValidationPolicy,
+    key: &mut String,
+    value: &str,
+    note: &mut String,
+    warnings: &mut Vec<String>,
+    failures: &mut Vec<String>,
+) -> bool {
+    if policy == ValidationPolicy::Off {
+        return true;
+    }
+
+    let issues = validate_secret(key, value, note);
+    let original = key.clone();
+    let describe = |issues: &[ValidationIssue]| {
+        issues
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    match policy {
+        ValidationPolicy::Off => {}
+        ValidationPolicy::Warn => {
+            warnings.extend(issues.iter().map(|issue| format!("{original:?}: {issue}")));
+        }
+        ValidationPolicy::Error => {
+            failures.extend(issues.iter().map(|issue| format!("{original:?}: {issue}")));
+        }
+        ValidationPolicy::Fix => {
+            if issues.contains(&ValidationIssue::EmptyKey) {
+                warnings.push(format!(
+                    "{original:?}: {} (dropping it)",
+                    ValidationIssue::EmptyKey
+                ));
+                return false;
+            }
+
+            let (key_issues, other_issues): (Vec<_>, Vec<_>) =
+                issues.into_iter().partition(|issue| {
+                    matches!(
+                        issue,
+                        ValidationIssue::InvalidKey | ValidationIssue::KeyTooLong(_)
+                    )
+                });
+            if !key_issues.is_empty() {
+                *key = sanitize_key(key);
+                warnings.push(format!(
+                    "{original:?}: {} (renamed to {key:?})",
+                    describe(&key_issues)
+                ));
+            }
+
+            for issue in other_issues {
+                match issue {
+                    ValidationIssue::NoteTooLong(_) => {
+                        *note = truncate(note, MAX_NOTE_LENGTH);
+                        warnings.push(format!("{original:?}: {issue} (truncated note)"));
+                    }
+                    _ => failures.push(format!("{original:?}: {issue}")),
+                }
+            }
+        }
+    }
+
+    true
+}
+
+/// Determines whether the given key is a valid POSIX environment variable name, consisting only
+/// of ASCII letters, digits and underscores, and not starting with a digit.
+pub fn is_posix_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Converts the given key into a valid POSIX environment variable name that fits within
+/// [`MAX_KEY_LENGTH`], by replacing invalid characters with underscores and prefixing keys that
+/// start with a digit with an underscore.
+pub fn sanitize_key(key: &str) -> String {
+    let mut sanitized = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+
+    truncate(&sanitized, MAX_KEY_LENGTH)
+}
+
+/// Truncates the given text to at most `max` characters.
+pub(crate) fn truncate(s: &str, max: usize) -> String {
+    s.chars().take(max).collect()
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use super::*;
+
+    #[test_case::test_case("KEY" => true; "accepts uppercase")]
+    #[test_case::test_case("key_2" => true; "accepts lowercase digits and underscores")]
+    #[test_case::test_case("_KEY" => true; "accepts leading underscore")]
+    #[test_case::test_case("" => false; "rejects empty key")]
+    #[test_case::test_case("2KEY" => false; "rejects leading digit")]
+    #[test_case::test_case("MY KEY" => false; "rejects spaces")]
+    #[test_case::test_case("MY-KEY" => false; "rejects dashes")]
+    #[test_case::test_case("KÉY" => false; "rejects non ascii letters")]
+    fn checks_posix_names(key: &str) -> bool {
+        is_posix_name(key)
+    }
+
+    #[test_case::test_case("MY KEY" => "MY_KEY"; "replaces spaces")]
+    #[test_case::test_case("my-key.name" => "my_key_name"; "replaces punctuation")]
+    #[test_case::test_case("2KEY" => "_2KEY"; "prefixes leading digit")]
+    #[test_case::test_case("KÉY" => "K_Y"; "replaces non ascii letters")]
+    fn sanitizes_keys(key: &str) -> String {
+        sanitize_key(key)
+    }
+
+    #[test]
+    fn sanitized_keys_fit_length_limit() {
+        let key = "K".repeat(MAX_KEY_LENGTH + 10);
+        assert_eq!(sanitize_key(&key).len(), MAX_KEY_LENGTH);
+    }
+
+    #[test_case::test_case("KEY", "value", "note" => Vec::<ValidationIssue>::new(); "accepts valid secret")]
+    #[test_case::test_case("", "value", "" => vec![ValidationIssue::EmptyKey]; "reports empty key")]
+    #[test_case::test_case("1 BAD", "value", "" => vec![ValidationIssue::InvalidKey]; "reports invalid key")]
+    #[test_case::test_case(&"K".repeat(MAX_KEY_LENGTH + 1), "", "" => vec![ValidationIssue::KeyTooLong(MAX_KEY_LENGTH + 1)]; "reports long key")]
+    #[test_case::test_case("KEY", &"v".repeat(MAX_VALUE_LENGTH + 1), "" => vec![ValidationIssue::ValueTooLong(MAX_VALUE_LENGTH + 1)]; "reports long value")]
+    #[test_case::test_case("KEY", "", &"n".repeat(MAX_NOTE_LENGTH + 1) => vec![ValidationIssue::NoteTooLong(MAX_NOTE_LENGTH + 1)]; "reports long note")]
+    #[test_case::test_case("KEY", &"é".repeat(MAX_VALUE_LENGTH), "" => Vec::<ValidationIssue>::new(); "counts characters rather than bytes")]
+    fn validates_secrets(key: &str, value: &str, note: &str) -> Vec<ValidationIssue> {
+        validate_secret(key, value, note)
+    }
+
+    #[test]
+    fn warns_once_about_fixed_keys() {
+        let mut key = format!("2{}", "K".repeat(MAX_KEY_LENGTH));
+        let mut note = String::new();
+        let (mut warnings, mut failures) = (vec![], vec![]);
+        let keep = enforce_policy(
+            ValidationPolicy::Fix,
+            &mut key,
+            "value",
+            &mut note,
+            &mut warnings,
+            &mut failures,
+        );
+
+        assert!(keep);
+        assert!(failures.is_empty());
+        assert_eq!(key.len(), MAX_KEY_LENGTH);
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].contains("key is not a valid")
+                && warnings[0].contains("exceeding the limit"),
+            "{warnings:?}"
+        );
+        assert!(
+            warnings[0].ends_with(&format!("(renamed to {key:?})")),
+            "{warnings:?}"
+        );
+    }
+
+    #[test]
+    fn skips_checks_when_off() {
+        let (mut warnings, mut failures) = (vec![], vec![]);
+        let keep = enforce_policy(
+            ValidationPolicy::Off,
+            &mut String::new(),
+            "value",
+            &mut String::new(),
+            &mut warnings,
+            &mut failures,
+        );
+
+        assert!(keep);
+        assert!(warnings.is_empty() && failures.is_empty());
+    }
+}